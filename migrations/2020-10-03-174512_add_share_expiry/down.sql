@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX share_expiry;
+
+CREATE TABLE old_shares (
+    link VARCHAR PRIMARY KEY NOT NULL,
+    path VARCHAR NOT NULL
+);
+
+INSERT INTO old_shares (link, path)
+SELECT link, path FROM shares;
+
+DROP TABLE shares;
+ALTER TABLE old_shares RENAME TO shares;
+
+CREATE INDEX shared_links ON shares (link);
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE shares ADD COLUMN expires_at BIGINT;
+
+CREATE INDEX share_expiry ON shares (expires_at);
\ No newline at end of file
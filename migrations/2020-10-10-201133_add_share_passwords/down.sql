@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE old_shares (
+    link VARCHAR PRIMARY KEY NOT NULL,
+    path VARCHAR NOT NULL,
+    expires_at BIGINT
+);
+
+INSERT INTO old_shares (link, path, expires_at)
+SELECT link, path, expires_at FROM shares;
+
+DROP TABLE shares;
+ALTER TABLE old_shares RENAME TO shares;
+
+CREATE INDEX shared_links ON shares (link);
+CREATE INDEX share_expiry ON shares (expires_at);
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE shares ADD COLUMN password VARCHAR;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE shares ADD COLUMN remaining_downloads INTEGER;
\ No newline at end of file
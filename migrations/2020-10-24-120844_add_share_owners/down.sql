@@ -0,0 +1,19 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX share_owners;
+
+CREATE TABLE old_shares (
+    link VARCHAR PRIMARY KEY NOT NULL,
+    path VARCHAR NOT NULL,
+    expires_at BIGINT,
+    password VARCHAR,
+    remaining_downloads INTEGER
+);
+
+INSERT INTO old_shares (link, path, expires_at, password, remaining_downloads)
+SELECT link, path, expires_at, password, remaining_downloads FROM shares;
+
+DROP TABLE shares;
+ALTER TABLE old_shares RENAME TO shares;
+
+CREATE INDEX shared_links ON shares (link);
+CREATE INDEX share_expiry ON shares (expires_at);
\ No newline at end of file
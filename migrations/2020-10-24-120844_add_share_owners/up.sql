@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE shares ADD COLUMN user_id INTEGER REFERENCES users (id);
+ALTER TABLE shares ADD COLUMN created_at BIGINT NOT NULL DEFAULT 0;
+
+CREATE INDEX share_owners ON shares (user_id);
\ No newline at end of file
@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE old_shares (
+    link VARCHAR PRIMARY KEY NOT NULL,
+    path VARCHAR NOT NULL,
+    expires_at BIGINT,
+    password VARCHAR,
+    remaining_downloads INTEGER,
+    user_id INTEGER REFERENCES users (id),
+    created_at BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO old_shares (link, path, expires_at, password, remaining_downloads, user_id, created_at)
+SELECT link, path, expires_at, password, remaining_downloads, user_id, created_at FROM shares;
+
+DROP TABLE shares;
+ALTER TABLE old_shares RENAME TO shares;
+
+CREATE INDEX shared_links ON shares (link);
+CREATE INDEX share_expiry ON shares (expires_at);
+CREATE INDEX share_owners ON shares (user_id);
\ No newline at end of file
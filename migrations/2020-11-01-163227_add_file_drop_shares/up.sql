@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE shares ADD COLUMN kind VARCHAR NOT NULL DEFAULT 'download';
+ALTER TABLE shares ADD COLUMN max_file_size BIGINT;
+ALTER TABLE shares ADD COLUMN max_total_size BIGINT;
+ALTER TABLE shares ADD COLUMN uploaded_bytes BIGINT NOT NULL DEFAULT 0;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX grant_grantees;
+DROP TABLE grants;
\ No newline at end of file
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE grants (
+    id INTEGER PRIMARY KEY NOT NULL,
+    owner_id INTEGER NOT NULL REFERENCES users (id),
+    grantee_id INTEGER NOT NULL REFERENCES users (id),
+    path VARCHAR NOT NULL,
+    permission VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL,
+    UNIQUE (owner_id, grantee_id, path)
+);
+
+CREATE INDEX grant_grantees ON grants (grantee_id);
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX group_member_users;
+DROP TABLE group_members;
+DROP TABLE groups;
\ No newline at end of file
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE groups (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name VARCHAR NOT NULL,
+    quota BIGINT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE group_members (
+    group_id INTEGER NOT NULL REFERENCES groups (id),
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    role VARCHAR NOT NULL,
+    PRIMARY KEY (group_id, user_id)
+);
+
+CREATE INDEX group_member_users ON group_members (user_id);
\ No newline at end of file
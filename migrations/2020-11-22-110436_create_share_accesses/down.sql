@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX share_access_links;
+DROP TABLE share_accesses;
\ No newline at end of file
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE share_accesses (
+    id INTEGER PRIMARY KEY NOT NULL,
+    link VARCHAR NOT NULL,
+    accessed_at BIGINT NOT NULL,
+    ip VARCHAR,
+    user_agent VARCHAR,
+    bytes_served BIGINT NOT NULL,
+    status INTEGER NOT NULL
+);
+
+CREATE INDEX share_access_links ON share_accesses (link, accessed_at);
\ No newline at end of file
@@ -0,0 +1,28 @@
+-- This file should undo anything in `up.sql`
+-- Shares converted since the migration only have a path relative to their owner's directory
+CREATE TABLE old_shares (
+    link VARCHAR PRIMARY KEY NOT NULL,
+    path VARCHAR NOT NULL,
+    expires_at BIGINT,
+    password VARCHAR,
+    remaining_downloads INTEGER,
+    user_id INTEGER REFERENCES users (id),
+    created_at BIGINT NOT NULL DEFAULT 0,
+    kind VARCHAR NOT NULL DEFAULT 'download',
+    max_file_size BIGINT,
+    max_total_size BIGINT,
+    uploaded_bytes BIGINT NOT NULL DEFAULT 0
+);
+INSERT INTO old_shares (link, path, expires_at, password, remaining_downloads, user_id,
+                        created_at, kind, max_file_size, max_total_size, uploaded_bytes)
+SELECT link, COALESCE(legacy_path, (SELECT path FROM files WHERE files.id = file_id), ''),
+       expires_at, password, remaining_downloads, user_id,
+       created_at, kind, max_file_size, max_total_size, uploaded_bytes
+FROM shares;
+DROP TABLE shares;
+ALTER TABLE old_shares RENAME TO shares;
+
+CREATE INDEX shared_links ON shares (link);
+CREATE INDEX share_expiry ON shares (expires_at);
+CREATE INDEX share_owners ON shares (user_id);
+DROP TABLE files;
\ No newline at end of file
@@ -0,0 +1,36 @@
+-- Your SQL goes here
+CREATE TABLE files (
+    id INTEGER PRIMARY KEY NOT NULL,
+    owner_id INTEGER NOT NULL REFERENCES users (id),
+    path VARCHAR NOT NULL,
+    UNIQUE (owner_id, path)
+);
+
+-- Converting the absolute paths of existing shares requires STORAGE_LOCATION, so they are kept
+-- in legacy_path until the server converts them when it starts
+CREATE TABLE new_shares (
+    link VARCHAR PRIMARY KEY NOT NULL,
+    file_id INTEGER REFERENCES files (id),
+    expires_at BIGINT,
+    password VARCHAR,
+    remaining_downloads INTEGER,
+    user_id INTEGER REFERENCES users (id),
+    created_at BIGINT NOT NULL DEFAULT 0,
+    kind VARCHAR NOT NULL DEFAULT 'download',
+    max_file_size BIGINT,
+    max_total_size BIGINT,
+    uploaded_bytes BIGINT NOT NULL DEFAULT 0,
+    legacy_path VARCHAR
+);
+INSERT INTO new_shares (link, expires_at, password, remaining_downloads, user_id, created_at,
+                        kind, max_file_size, max_total_size, uploaded_bytes, legacy_path)
+SELECT link, expires_at, password, remaining_downloads, user_id, created_at,
+       kind, max_file_size, max_total_size, uploaded_bytes, path
+FROM shares;
+DROP TABLE shares;
+ALTER TABLE new_shares RENAME TO shares;
+
+CREATE INDEX shared_links ON shares (link);
+CREATE INDEX share_expiry ON shares (expires_at);
+CREATE INDEX share_owners ON shares (user_id);
+CREATE INDEX share_files ON shares (file_id);
\ No newline at end of file
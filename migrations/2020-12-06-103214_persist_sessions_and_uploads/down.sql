@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE pending_uploads;
+DROP TABLE sessions;
\ No newline at end of file
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TABLE sessions (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    public_id VARCHAR NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    created_at BIGINT NOT NULL,
+    last_seen BIGINT NOT NULL,
+    user_agent VARCHAR,
+    ip VARCHAR
+);
+
+CREATE INDEX session_users ON sessions (user_id);
+
+CREATE TABLE pending_uploads (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    path VARCHAR NOT NULL,
+    user_id INTEGER REFERENCES users (id),
+    share_link VARCHAR,
+    quota_user_id INTEGER,
+    quota_group_id INTEGER,
+    created_at BIGINT NOT NULL
+);
\ No newline at end of file
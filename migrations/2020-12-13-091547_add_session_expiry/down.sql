@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE old_sessions (
+    id VARCHAR PRIMARY KEY NOT NULL,
+    public_id VARCHAR NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    created_at BIGINT NOT NULL,
+    last_seen BIGINT NOT NULL,
+    user_agent VARCHAR,
+    ip VARCHAR
+);
+
+INSERT INTO old_sessions (id, public_id, user_id, created_at, last_seen, user_agent, ip)
+SELECT id, public_id, user_id, created_at, last_seen, user_agent, ip FROM sessions;
+
+DROP TABLE sessions;
+ALTER TABLE old_sessions RENAME TO sessions;
+
+CREATE INDEX session_users ON sessions (user_id);
\ No newline at end of file
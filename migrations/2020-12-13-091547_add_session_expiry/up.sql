@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE sessions ADD COLUMN expires_at BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE sessions ADD COLUMN idle_timeout BIGINT;
+
+-- Sessions used to be kept for one week after their creation
+UPDATE sessions SET expires_at = created_at + 604800;
+
+CREATE INDEX session_expiry ON sessions (expires_at);
\ No newline at end of file
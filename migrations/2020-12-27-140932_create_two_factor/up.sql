@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE totp_secrets (
+    user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id),
+    secret VARCHAR NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT 0,
+    last_used_step BIGINT,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE recovery_codes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    code_hash VARCHAR NOT NULL
+);
+
+CREATE INDEX recovery_code_users ON recovery_codes (user_id);
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE password_resets (
+    token_hash VARCHAR PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX password_reset_users ON password_resets (user_id);
\ No newline at end of file
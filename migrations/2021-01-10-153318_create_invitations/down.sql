@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE email_verifications;
+DROP TABLE invitations;
+
+CREATE TABLE old_users (
+    id INTEGER PRIMARY KEY NOT NULL,
+    email VARCHAR NOT NULL UNIQUE,
+    display_name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL
+);
+
+INSERT INTO old_users (id, email, display_name, password)
+SELECT id, email, display_name, password FROM users;
+
+DROP TABLE users;
+ALTER TABLE old_users RENAME TO users;
\ No newline at end of file
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+CREATE TABLE invitations (
+    id INTEGER PRIMARY KEY NOT NULL,
+    code_hash VARCHAR NOT NULL UNIQUE,
+    created_by INTEGER NOT NULL REFERENCES users (id),
+    email VARCHAR,
+    max_uses INTEGER NOT NULL,
+    uses INTEGER NOT NULL DEFAULT 0,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT
+);
+
+CREATE TABLE email_verifications (
+    token_hash VARCHAR PRIMARY KEY NOT NULL,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    email VARCHAR NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE INDEX email_verification_users ON email_verifications (user_id);
+
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT 0;
+
+-- Accounts created before verification existed are trusted
+UPDATE users SET email_verified = 1;
\ No newline at end of file
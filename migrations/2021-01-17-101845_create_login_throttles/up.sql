@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE login_throttles (
+    kind VARCHAR NOT NULL,
+    value VARCHAR NOT NULL,
+    failures INTEGER NOT NULL,
+    last_failure BIGINT NOT NULL,
+    locked_until BIGINT,
+    PRIMARY KEY (kind, value)
+);
\ No newline at end of file
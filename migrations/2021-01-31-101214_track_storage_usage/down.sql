@@ -0,0 +1,30 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE old_users (
+    id INTEGER PRIMARY KEY NOT NULL,
+    email VARCHAR NOT NULL UNIQUE,
+    display_name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    email_verified BOOLEAN NOT NULL DEFAULT 0,
+    role VARCHAR NOT NULL DEFAULT 'user',
+    disabled BOOLEAN NOT NULL DEFAULT 0,
+    quota BIGINT
+);
+
+INSERT INTO old_users (id, email, display_name, password, email_verified, role, disabled, quota)
+SELECT id, email, display_name, password, email_verified, role, disabled, quota FROM users;
+
+DROP TABLE users;
+ALTER TABLE old_users RENAME TO users;
+
+CREATE TABLE old_groups (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name VARCHAR NOT NULL,
+    quota BIGINT,
+    created_at BIGINT NOT NULL
+);
+
+INSERT INTO old_groups (id, name, quota, created_at)
+SELECT id, name, quota, created_at FROM groups;
+
+DROP TABLE groups;
+ALTER TABLE old_groups RENAME TO groups;
\ No newline at end of file
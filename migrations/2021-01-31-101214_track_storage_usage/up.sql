@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- The server counts the space used by each user and group when it starts, and keeps these
+-- counts up to date as files are uploaded
+ALTER TABLE users ADD COLUMN used_bytes BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE groups ADD COLUMN used_bytes BIGINT NOT NULL DEFAULT 0;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- The keys of the sessions cannot be recovered from their hashes
+DELETE FROM sessions;
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Sessions are now stored under the hash of their key, which cannot be computed for the existing
+-- ones without their cookies, so everyone has to log in again
+DELETE FROM sessions;
\ No newline at end of file
@@ -0,0 +1,19 @@
+-- This file should undo anything in `up.sql`
+-- Email addresses stay in lowercase
+CREATE TABLE old_users (
+    id INTEGER PRIMARY KEY NOT NULL,
+    email VARCHAR NOT NULL UNIQUE,
+    display_name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    email_verified BOOLEAN NOT NULL DEFAULT 0,
+    role VARCHAR NOT NULL DEFAULT 'user',
+    disabled BOOLEAN NOT NULL DEFAULT 0,
+    quota BIGINT,
+    used_bytes BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO old_users (id, email, display_name, password, email_verified, role, disabled, quota, used_bytes)
+SELECT id, email, display_name, password, email_verified, role, disabled, quota, used_bytes FROM users;
+
+DROP TABLE users;
+ALTER TABLE old_users RENAME TO users;
\ No newline at end of file
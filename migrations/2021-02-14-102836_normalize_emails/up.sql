@@ -0,0 +1,36 @@
+-- Your SQL goes here
+-- Email addresses are stored in lowercase and compared without case from now on. An account whose
+-- address only differs by its case from the one of an older account cannot keep it, so its id is
+-- appended to it and it has to be verified again.
+CREATE TABLE new_users (
+    id INTEGER PRIMARY KEY NOT NULL,
+    email VARCHAR NOT NULL UNIQUE COLLATE NOCASE,
+    display_name VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    email_verified BOOLEAN NOT NULL DEFAULT 0,
+    role VARCHAR NOT NULL DEFAULT 'user',
+    disabled BOOLEAN NOT NULL DEFAULT 0,
+    quota BIGINT,
+    used_bytes BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO new_users (id, email, display_name, password, email_verified, role, disabled, quota, used_bytes)
+SELECT id, lower(email), display_name, password, email_verified, role, disabled, quota, used_bytes
+FROM users
+WHERE NOT EXISTS (
+    SELECT 1 FROM users AS older WHERE lower(older.email) = lower(users.email) AND older.id < users.id
+);
+
+INSERT INTO new_users (id, email, display_name, password, email_verified, role, disabled, quota, used_bytes)
+SELECT id, lower(email) || '#' || id, display_name, password, 0, role, disabled, quota, used_bytes
+FROM users
+WHERE EXISTS (
+    SELECT 1 FROM users AS older WHERE lower(older.email) = lower(users.email) AND older.id < users.id
+);
+
+DROP TABLE users;
+ALTER TABLE new_users RENAME TO users;
+
+UPDATE invitations SET email = lower(email);
+UPDATE email_verifications SET email = lower(email);
+UPDATE OR REPLACE login_throttles SET value = lower(value) WHERE kind = 'account';
\ No newline at end of file
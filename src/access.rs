@@ -0,0 +1,261 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::models::file::{JsonPath, PendingUpload};
+use crate::models::grant::Permission;
+use crate::models::group::{Group, GroupMember, GroupRole};
+use crate::models::user::User;
+use crate::utils::{self, Namespace};
+use diesel::SqliteConnection;
+use rocket::http::Status;
+use std::path::{Path, PathBuf};
+
+/// Resolves a path sent by a user to the location of the file or directory on disk.
+///
+/// Paths are relative to the user's storage directory, unless they come with a grant or a group.
+/// With a grant, they are relative to what the grant's owner shared and the grant must give at
+/// least the required permission. With a group, they are relative to the group's directory and
+/// the user's role must give that permission. This is the only place where file routes decide
+/// what a user may access.
+pub fn resolve(
+    path: JsonPath,
+    user: &User,
+    required: Permission,
+    conn: &SqliteConnection,
+) -> Result<PathBuf, ApiError> {
+    let (_, path) = resolve_with_namespace(path, user, required, conn)?;
+
+    Ok(path)
+}
+
+/// Like `resolve`, but also returns the namespace the path belongs to.
+pub fn resolve_with_namespace(
+    path: JsonPath,
+    user: &User,
+    required: Permission,
+    conn: &SqliteConnection,
+) -> Result<(Namespace, PathBuf), ApiError> {
+    match (path.grant, path.group) {
+        (None, None) => Ok((Namespace::User(user.id), own_path(path, user)?)),
+        (Some(grant_id), None) => {
+            let relative_path = path.to_pathbuf()?;
+            let grant =
+                db::grant::get_received_grant(grant_id, user.id, conn)?.ok_or_else(|| {
+                    CustomError::new("This grant does not exist".to_string(), Status::NotFound)
+                })?;
+            if !grant.permission().allows(required) {
+                Err(CustomError::new(
+                    "This grant does not allow changes".to_string(),
+                    Status::Forbidden,
+                ))?;
+            }
+
+            let namespace = Namespace::User(grant.owner_id);
+            let shared_path = utils::root_path(namespace).join(&grant.path);
+            if relative_path.as_os_str().is_empty() {
+                Ok((namespace, shared_path))
+            } else {
+                Ok((namespace, shared_path.join(relative_path)))
+            }
+        }
+        (None, Some(group_id)) => {
+            let membership = find_membership(group_id, user, conn)?;
+            if !membership.role().permission().allows(required) {
+                Err(CustomError::new(
+                    "Viewers cannot change the files of a group".to_string(),
+                    Status::Forbidden,
+                ))?;
+            }
+
+            let namespace = Namespace::Group(group_id);
+            Ok((
+                namespace,
+                utils::root_path(namespace).join(path.to_pathbuf()?),
+            ))
+        }
+        (Some(_), Some(_)) => Err(CustomError::new(
+            "Paths cannot refer to both a grant and a group".to_string(),
+            Status::BadRequest,
+        ))?,
+    }
+}
+
+/// Resolves a path which must be in the user's own storage directory, such as the path of
+/// something they want to share.
+pub fn own_path(path: JsonPath, user: &User) -> Result<PathBuf, ApiError> {
+    if path.grant.is_some() || path.group.is_some() {
+        Err(CustomError::new(
+            "Only your own files can be shared".to_string(),
+            Status::Forbidden,
+        ))?;
+    }
+
+    Ok(utils::root_path(Namespace::User(user.id)).join(path.to_pathbuf()?))
+}
+
+/// Checks that a user may still send the data of an upload they created, as the grant or the
+/// group membership which let them create it may have been revoked since.
+pub fn check_pending_upload(
+    upload: &PendingUpload,
+    user: &User,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let path = Path::new(&upload.path);
+    let allowed = match upload.namespace() {
+        Some(Namespace::User(owner_id)) if owner_id == user.id => true,
+        Some(Namespace::User(owner_id)) => {
+            let owner_root = utils::root_path(Namespace::User(owner_id));
+            db::grant::get_received_grants(user.id, conn)?
+                .iter()
+                .any(|grant| {
+                    grant.owner_id == owner_id
+                        && grant.permission().allows(Permission::Write)
+                        && path.starts_with(owner_root.join(&grant.path))
+                })
+        }
+        Some(Namespace::Group(group_id)) => {
+            match db::group::get_membership(group_id, user.id, conn)? {
+                Some(membership) => membership.role().permission().allows(Permission::Write),
+                None => false,
+            }
+        }
+        // Uploads created before namespaces were recorded can only be to the user's own files
+        None => path.starts_with(utils::root_path(Namespace::User(user.id))),
+    };
+    if !allowed {
+        Err(CustomError::new(
+            "You are not allowed to upload files there anymore".to_string(),
+            Status::Forbidden,
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the number of bytes which can still be written to a namespace, if it is limited.
+pub fn remaining_space(
+    namespace: Namespace,
+    conn: &SqliteConnection,
+) -> Result<Option<u64>, ApiError> {
+    let (quota, used_bytes) = match namespace {
+        Namespace::User(user_id) => {
+            let user = db::user::get_by_id(user_id, conn)?.ok_or(ApiError::NotFound)?;
+            (user.quota, user.used_bytes)
+        }
+        Namespace::Group(group_id) => {
+            let group = db::group::get_group(group_id, conn)?.ok_or(ApiError::NotFound)?;
+            (group.quota, group.used_bytes)
+        }
+    };
+
+    Ok(quota.map(|quota| space_left(quota, used_bytes)))
+}
+
+/// Counts bytes written to a namespace, or freed when negative, unless that would go over its
+/// quota. Returns whether they were counted.
+pub fn record_usage(
+    namespace: Namespace,
+    bytes: i64,
+    conn: &SqliteConnection,
+) -> Result<bool, ApiError> {
+    match namespace {
+        Namespace::User(user_id) => db::user::add_used_bytes(user_id, bytes, conn),
+        Namespace::Group(group_id) => db::group::add_used_bytes(group_id, bytes, conn),
+    }
+}
+
+/// Counts the space taken by the files of every user and group again, to account for changes
+/// made directly on disk. This walks through all the stored files, so it only runs when the
+/// server starts.
+pub fn recount_usage(conn: &SqliteConnection) -> Result<(), ApiError> {
+    for user in db::user::get_all(conn)? {
+        let used_bytes = utils::dir_size(&utils::root_path(Namespace::User(user.id)));
+        db::user::set_used_bytes(user.id, used_bytes.min(i64::MAX as u64) as i64, conn)?;
+    }
+    for group in db::group::get_all(conn)? {
+        let used_bytes = utils::dir_size(&utils::root_path(Namespace::Group(group.id)));
+        db::group::set_used_bytes(group.id, used_bytes.min(i64::MAX as u64) as i64, conn)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the number of bytes left under a quota.
+pub fn space_left(quota: i64, used_bytes: i64) -> u64 {
+    quota.saturating_sub(used_bytes).max(0) as u64
+}
+
+/// Returns the largest number of bytes a file can have to fit in all of the given limits.
+pub fn size_limit(limits: &[Option<u64>]) -> u64 {
+    limits
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+        // Leave room to read one byte past the limit
+        .unwrap_or(u64::MAX - 1)
+}
+
+/// Finds a group which the user is a member of, along with their membership.
+pub fn find_group(
+    group_id: i32,
+    user: &User,
+    conn: &SqliteConnection,
+) -> Result<(Group, GroupMember), ApiError> {
+    let membership = find_membership(group_id, user, conn)?;
+    let group = db::group::get_group(group_id, conn)?.ok_or(ApiError::NotFound)?;
+
+    Ok((group, membership))
+}
+
+/// Finds a group which the user owns, and can therefore manage.
+pub fn find_owned_group(
+    group_id: i32,
+    user: &User,
+    conn: &SqliteConnection,
+) -> Result<(Group, GroupMember), ApiError> {
+    let (group, membership) = find_group(group_id, user, conn)?;
+    if membership.role() != GroupRole::Owner {
+        Err(CustomError::new(
+            "Only owners can manage a group".to_string(),
+            Status::Forbidden,
+        ))?;
+    }
+
+    Ok((group, membership))
+}
+
+fn find_membership(
+    group_id: i32,
+    user: &User,
+    conn: &SqliteConnection,
+) -> Result<GroupMember, ApiError> {
+    // Groups the user is not a member of are reported as missing, to avoid revealing them
+    let membership = db::group::get_membership(group_id, user.id, conn)?.ok_or_else(|| {
+        CustomError::new("This group does not exist".to_string(), Status::NotFound)
+    })?;
+
+    Ok(membership)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_space_left() {
+        assert_eq!(space_left(100, 30), 70);
+        assert_eq!(space_left(100, 100), 0);
+        // Quotas can be lowered below what is already used
+        assert_eq!(space_left(100, 250), 0);
+        assert_eq!(space_left(i64::MAX, -1), i64::MAX as u64);
+        assert_eq!(space_left(0, i64::MIN), i64::MAX as u64);
+    }
+
+    #[test]
+    fn uses_the_smallest_size_limit() {
+        assert_eq!(size_limit(&[Some(10), None, Some(3)]), 3);
+        assert_eq!(size_limit(&[None, Some(0)]), 0);
+        assert_eq!(size_limit(&[None, None]), u64::MAX - 1);
+        assert_eq!(size_limit(&[]), u64::MAX - 1);
+    }
+}
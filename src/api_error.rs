@@ -34,6 +34,41 @@ impl CustomError {
 #[derive(serde::Serialize, Debug)]
 pub struct ErrorResponse {
     message: String,
+    /// What is wrong with each invalid field of the request, when there are any
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<ValidationError>,
+}
+
+/// A reason why a field of a request is invalid.
+#[derive(serde::Serialize, Debug)]
+pub struct ValidationError {
+    pub field: String,
+    /// Identifies the reason for clients, such as `too_short`
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    /// Builds the error sent when some fields of a request are invalid, which lists everything
+    /// that is wrong with them.
+    pub fn validation(errors: Vec<ValidationError>) -> ApiError {
+        ApiError::Custom(status::Custom(
+            Status::BadRequest,
+            Json(ErrorResponse {
+                message: "Some fields are invalid".to_string(),
+                errors,
+            }),
+        ))
+    }
+
+    /// Returns the HTTP status of the response sent for this error.
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::NotFound => Status::NotFound,
+            ApiError::Custom(error) => error.0,
+            _ => Status::InternalServerError,
+        }
+    }
 }
 
 impl<'r> Responder<'r> for ApiError {
@@ -95,6 +130,7 @@ impl From<CustomError> for ApiError {
             e.status,
             Json(ErrorResponse {
                 message: e.message.to_string(),
+                errors: Vec::new(),
             }),
         ))
     }
@@ -108,6 +144,14 @@ pub fn unauthorized(_req: &rocket::Request) -> ApiError {
     })
 }
 
+#[catch(403)]
+pub fn forbidden(_req: &rocket::Request) -> ApiError {
+    ApiError::from(CustomError {
+        status: Status::Forbidden,
+        message: "You are not allowed to access this resource".to_string(),
+    })
+}
+
 #[catch(404)]
 pub fn not_found(_req: &rocket::Request) -> ApiError {
     ApiError::from(CustomError {
@@ -1,11 +1,42 @@
 use crate::api_error::{ApiError, CustomError};
-use crate::models::file::Share;
+use crate::db::last_insert_rowid;
+use crate::models::file::{
+    NewShareAccess, NewTrackedFile, PendingUpload, Share, ShareAccess, ShareAccessSummary,
+    TrackedFile,
+};
+use crate::schema::files::id as file_id_column;
+use crate::schema::files::owner_id as file_owner_id_column;
+use crate::schema::files::path as file_path_column;
+use crate::schema::files::table as files_table;
+use crate::schema::pending_uploads::created_at as upload_created_at_column;
+use crate::schema::pending_uploads::id as upload_id_column;
+use crate::schema::pending_uploads::table as pending_uploads_table;
+use crate::schema::share_accesses::accessed_at as accessed_at_column;
+use crate::schema::share_accesses::bytes_served as bytes_served_column;
+use crate::schema::share_accesses::id as access_id_column;
+use crate::schema::share_accesses::ip as ip_column;
+use crate::schema::share_accesses::link as access_link_column;
+use crate::schema::share_accesses::status as status_column;
+use crate::schema::share_accesses::table as share_accesses_table;
+use crate::schema::share_accesses::user_agent as user_agent_column;
 use crate::schema::shares::table as shares_table;
+use crate::schema::shares::created_at as created_at_column;
+use crate::schema::shares::expires_at as expires_at_column;
+use crate::schema::shares::file_id as share_file_id_column;
+use crate::schema::shares::legacy_path as legacy_path_column;
 use crate::schema::shares::link as link_column;
-use diesel::insert_into;
+use crate::schema::shares::max_total_size as max_total_size_column;
+use crate::schema::shares::remaining_downloads as remaining_downloads_column;
+use crate::schema::shares::uploaded_bytes as uploaded_bytes_column;
+use crate::schema::shares::user_id as user_id_column;
+use crate::utils;
+use diesel::{delete, insert_into, insert_or_ignore_into, sql_query, update};
 use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::sql_types::Text;
 use diesel::SqliteConnection;
 use rocket::http::Status;
+use std::path::{Component, Path};
 
 pub fn get_share(link: &str, conn: &SqliteConnection) -> Result<Option<Share>, ApiError> {
     let result = shares_table
@@ -18,11 +49,360 @@ pub fn get_share(link: &str, conn: &SqliteConnection) -> Result<Option<Share>, A
     Ok(result)
 }
 
+/// Gets the shares created by a user, most recent first, along with the files they share.
+pub fn get_user_shares(
+    user_id: i32,
+    conn: &SqliteConnection,
+) -> Result<Vec<(Share, Option<TrackedFile>)>, ApiError> {
+    let result = shares_table
+        .left_join(files_table)
+        .filter(user_id_column.eq(user_id))
+        .order(created_at_column.desc())
+        .load::<(Share, Option<TrackedFile>)>(conn)?;
+
+    Ok(result)
+}
+
+pub fn get_tracked_file(id: i32, conn: &SqliteConnection) -> Result<Option<TrackedFile>, ApiError> {
+    let result = files_table
+        .filter(file_id_column.eq(id))
+        .limit(1)
+        .load::<TrackedFile>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+/// Gets the tracked file at a path relative to its owner's directory, tracking it if needed.
+pub fn track_file(
+    owner_id: i32,
+    path: &str,
+    conn: &SqliteConnection,
+) -> Result<TrackedFile, ApiError> {
+    let file = conn.transaction::<_, diesel::result::Error, _>(|| {
+        insert_or_ignore_into(files_table)
+            .values(&NewTrackedFile {
+                owner_id,
+                path: path.to_string(),
+            })
+            .execute(conn)?;
+
+        files_table
+            .filter(file_owner_id_column.eq(owner_id))
+            .filter(file_path_column.eq(path))
+            .first::<TrackedFile>(conn)
+    })?;
+
+    Ok(file)
+}
+
+/// Updates the paths of the tracked files affected by moving `from` to `to`, which are both
+/// relative to the owner's directory.
+///
+/// Tracked files which were already at the destination no longer exist, since the destination
+/// had to be free for the move to happen, so they are forgotten. This makes the shares which
+/// referred to them stop working, instead of pointing to what was moved there.
+pub fn record_file_move(
+    owner_id: i32,
+    from: &str,
+    to: &str,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let moved_files = files_table
+        .filter(file_owner_id_column.eq(owner_id))
+        .load::<TrackedFile>(conn)?
+        .into_iter()
+        .filter_map(|file| utils::moved_path(&file.path, from, to).map(|path| (file.id, path)))
+        .collect::<Vec<(i32, String)>>();
+    let moved_ids = moved_files.iter().map(|(id, _)| *id).collect::<Vec<i32>>();
+
+    for (id, path) in &moved_files {
+        delete(
+            files_table
+                .filter(file_owner_id_column.eq(owner_id))
+                .filter(file_path_column.eq(path))
+                .filter(file_id_column.ne_all(&moved_ids)),
+        )
+        .execute(conn)?;
+        update(files_table.filter(file_id_column.eq(id)))
+            .set(file_path_column.eq(path))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the tracked files which are no longer shared.
+pub fn delete_unused_files(conn: &SqliteConnection) -> Result<usize, ApiError> {
+    let shared_files = shares_table
+        .select(share_file_id_column)
+        .filter(share_file_id_column.is_not_null());
+    let deleted =
+        delete(files_table.filter(file_id_column.nullable().ne_all(shared_files))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+/// Converts the absolute paths of shares created before files were tracked to tracked files.
+///
+/// Shares whose path is not inside a user's directory in `storage_root` are left as they are,
+/// so that they can still be converted once `STORAGE_LOCATION` points to where they are stored.
+/// Returns the links of those shares.
+pub fn convert_legacy_shares(
+    storage_root: &Path,
+    conn: &SqliteConnection,
+) -> Result<Vec<String>, ApiError> {
+    let legacy_shares = shares_table
+        .filter(legacy_path_column.is_not_null())
+        .load::<Share>(conn)?;
+
+    let mut unconverted = vec![];
+    for share in legacy_shares {
+        let legacy_path = share.legacy_path.clone().unwrap_or_default();
+        let mut components = match Path::new(&legacy_path).strip_prefix(storage_root) {
+            Ok(relative) => relative.components(),
+            Err(_) => {
+                unconverted.push(share.link);
+                continue;
+            }
+        };
+        let owner_id = match components.next() {
+            Some(Component::Normal(owner)) => owner.to_str().and_then(|o| o.parse::<i32>().ok()),
+            _ => None,
+        };
+        let (owner_id, path) = match (owner_id, components.as_path().to_str()) {
+            (Some(owner_id), Some(path)) => (owner_id, path.to_string()),
+            _ => {
+                unconverted.push(share.link);
+                continue;
+            }
+        };
+
+        let file = track_file(owner_id, &path, conn)?;
+        update(shares_table.filter(link_column.eq(&share.link)))
+            .set((
+                share_file_id_column.eq(file.id),
+                user_id_column.eq(share.user_id.unwrap_or(owner_id)),
+                legacy_path_column.eq(None::<String>),
+            ))
+            .execute(conn)?;
+    }
+
+    Ok(unconverted)
+}
+
+/// Saves a new share, which fails with a conflict when its link is already used by another one.
 pub fn save_share(share: &Share, conn: &SqliteConnection) -> Result<(), ApiError> {
     insert_into(shares_table)
         .values(share)
         .execute(conn)
-        .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+        .map_err(|e| match e {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => CustomError::new(
+                "This slug is already used by another share".to_string(),
+                Status::Conflict,
+            ),
+            e => CustomError::new(e.to_string(), Status::InternalServerError),
+        })?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Uses up one of the downloads of a share with a limited number of downloads.
+///
+/// The check and the decrement happen in a single statement, so concurrent downloads cannot
+/// exceed the limit. Returns whether a download was still available.
+pub fn consume_share_download(link: &str, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let updated = update(
+        shares_table
+            .filter(link_column.eq(link))
+            .filter(remaining_downloads_column.gt(0)),
+    )
+    .set(remaining_downloads_column.eq(remaining_downloads_column - 1))
+    .execute(conn)?;
+
+    Ok(updated == 1)
+}
+
+/// Adds the size of a file uploaded to a file drop to the share's total.
+///
+/// Like `consume_share_download`, the limit is checked in the same statement as the update,
+/// so concurrent uploads cannot go over it. Returns whether the file fit in the limit.
+pub fn record_drop_upload(link: &str, bytes: i64, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let updated = update(
+        shares_table.filter(link_column.eq(link)).filter(
+            max_total_size_column
+                .is_null()
+                .or(max_total_size_column.ge((uploaded_bytes_column + bytes).nullable())),
+        ),
+    )
+    .set(uploaded_bytes_column.eq(uploaded_bytes_column + bytes))
+    .execute(conn)?;
+
+    Ok(updated == 1)
+}
+
+/// Deletes a share owned by the given user, or a share without owner when `user_id` is `None`,
+/// returning whether it existed.
+pub fn delete_user_share(
+    link: &str,
+    user_id: Option<i32>,
+    conn: &SqliteConnection,
+) -> Result<bool, ApiError> {
+    let deleted = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let owner = shares_table
+            .select(user_id_column)
+            .filter(link_column.eq(link))
+            .first::<Option<i32>>(conn)
+            .optional()?;
+        if owner != Some(user_id) {
+            return Ok(false);
+        }
+        delete(shares_table.filter(link_column.eq(link))).execute(conn)?;
+        delete(share_accesses_table.filter(access_link_column.eq(link))).execute(conn)?;
+
+        Ok(true)
+    })?;
+
+    Ok(deleted)
+}
+
+/// Gets the shares which have no owner, because they were created before owners were recorded
+/// and their path could not be converted.
+pub fn get_unowned_shares(conn: &SqliteConnection) -> Result<Vec<Share>, ApiError> {
+    let result = shares_table
+        .filter(user_id_column.is_null())
+        .order(link_column.asc())
+        .load::<Share>(conn)?;
+
+    Ok(result)
+}
+
+pub fn delete_expired_shares(now: i64, conn: &SqliteConnection) -> Result<usize, ApiError> {
+    let deleted = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let expired_links = shares_table
+            .select(link_column)
+            .filter(expires_at_column.le(now));
+        delete(share_accesses_table.filter(access_link_column.eq_any(expired_links)))
+            .execute(conn)?;
+
+        delete(shares_table.filter(expires_at_column.le(now))).execute(conn)
+    })?;
+
+    Ok(deleted)
+}
+
+/// Records an access to a share, returning its ID.
+pub fn record_share_access(
+    access: &NewShareAccess,
+    conn: &SqliteConnection,
+) -> Result<i32, ApiError> {
+    let id = conn.transaction::<_, diesel::result::Error, _>(|| {
+        insert_into(share_accesses_table)
+            .values(access)
+            .execute(conn)?;
+        diesel::select(last_insert_rowid).get_result::<i64>(conn)
+    })?;
+
+    Ok(id as i32)
+}
+
+/// Records the number of bytes actually sent for an access to a share, once it is over.
+pub fn set_bytes_served(id: i32, bytes: i64, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(share_accesses_table.filter(access_id_column.eq(id)))
+        .set(bytes_served_column.eq(bytes))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Computes the statistics of the accesses to a share. Accesses fail when their status is 400
+/// or more, like `ShareAccess::is_success` decides.
+pub fn get_share_stats(
+    link: &str,
+    conn: &SqliteConnection,
+) -> Result<ShareAccessSummary, ApiError> {
+    let summary = sql_query(
+        "SELECT COUNT(*) AS accesses, \
+                COALESCE(SUM(status >= 400), 0) AS failed_accesses, \
+                COALESCE(SUM(bytes_served), 0) AS bytes_served, \
+                COUNT(DISTINCT ip) AS unique_visitors, \
+                MIN(accessed_at) AS first_access, \
+                MAX(accessed_at) AS last_access \
+         FROM share_accesses WHERE link = ?",
+    )
+    .bind::<Text, _>(link)
+    .get_result::<ShareAccessSummary>(conn)?;
+
+    Ok(summary)
+}
+
+/// Gets the accesses to a share, most recent first, optionally limited to the given number.
+pub fn get_share_accesses(
+    link: &str,
+    limit: Option<i64>,
+    conn: &SqliteConnection,
+) -> Result<Vec<ShareAccess>, ApiError> {
+    let query = share_accesses_table
+        .filter(access_link_column.eq(link))
+        .order((accessed_at_column.desc(), access_id_column.desc()))
+        .select((
+            accessed_at_column,
+            ip_column,
+            user_agent_column,
+            bytes_served_column,
+            status_column,
+        ));
+    let result = match limit {
+        Some(limit) => query.limit(limit).load::<ShareAccess>(conn)?,
+        None => query.load::<ShareAccess>(conn)?,
+    };
+
+    Ok(result)
+}
+
+pub fn delete_old_share_accesses(before: i64, conn: &SqliteConnection) -> Result<usize, ApiError> {
+    let deleted =
+        delete(share_accesses_table.filter(accessed_at_column.lt(before))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+pub fn save_pending_upload(
+    upload: &PendingUpload,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    insert_into(pending_uploads_table)
+        .values(upload)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn get_pending_upload(
+    id: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<PendingUpload>, ApiError> {
+    let result = pending_uploads_table
+        .filter(upload_id_column.eq(id))
+        .limit(1)
+        .load::<PendingUpload>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+pub fn delete_pending_upload(id: &str, conn: &SqliteConnection) -> Result<(), ApiError> {
+    delete(pending_uploads_table.filter(upload_id_column.eq(id))).execute(conn)?;
+
+    Ok(())
+}
+
+/// Deletes the pending uploads created before the given timestamp.
+pub fn delete_old_pending_uploads(before: i64, conn: &SqliteConnection) -> Result<usize, ApiError> {
+    let deleted =
+        delete(pending_uploads_table.filter(upload_created_at_column.lt(before))).execute(conn)?;
+
+    Ok(deleted)
+}
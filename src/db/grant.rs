@@ -0,0 +1,124 @@
+use crate::api_error::ApiError;
+use crate::models::grant::{Grant, NewGrant};
+use crate::schema::grants::created_at as created_at_column;
+use crate::schema::grants::grantee_id as grantee_id_column;
+use crate::schema::grants::id as id_column;
+use crate::schema::grants::owner_id as owner_id_column;
+use crate::schema::grants::path as path_column;
+use crate::schema::grants::permission as permission_column;
+use crate::schema::grants::table as grants_table;
+use crate::utils;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use diesel::{delete, insert_or_ignore_into, update};
+
+/// Gives a user access to a path, or changes the permission they already have on it.
+pub fn create(grant: &NewGrant, conn: &SqliteConnection) -> Result<(), ApiError> {
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        insert_or_ignore_into(grants_table)
+            .values(grant)
+            .execute(conn)?;
+        update(
+            grants_table
+                .filter(owner_id_column.eq(grant.owner_id))
+                .filter(grantee_id_column.eq(grant.grantee_id))
+                .filter(path_column.eq(&grant.path)),
+        )
+        .set(permission_column.eq(&grant.permission))
+        .execute(conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Gets a grant given to the specified user, so that it can be used to access the owner's files.
+pub fn get_received_grant(
+    id: i32,
+    grantee_id: i32,
+    conn: &SqliteConnection,
+) -> Result<Option<Grant>, ApiError> {
+    let result = grants_table
+        .filter(id_column.eq(id))
+        .filter(grantee_id_column.eq(grantee_id))
+        .limit(1)
+        .load::<Grant>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+pub fn get_given_grants(owner_id: i32, conn: &SqliteConnection) -> Result<Vec<Grant>, ApiError> {
+    let result = grants_table
+        .filter(owner_id_column.eq(owner_id))
+        .order(created_at_column.desc())
+        .load::<Grant>(conn)?;
+
+    Ok(result)
+}
+
+pub fn get_received_grants(
+    grantee_id: i32,
+    conn: &SqliteConnection,
+) -> Result<Vec<Grant>, ApiError> {
+    let result = grants_table
+        .filter(grantee_id_column.eq(grantee_id))
+        .order(created_at_column.desc())
+        .load::<Grant>(conn)?;
+
+    Ok(result)
+}
+
+/// Deletes a grant given by the specified user, returning whether it existed.
+pub fn delete_given_grant(
+    id: i32,
+    owner_id: i32,
+    conn: &SqliteConnection,
+) -> Result<bool, ApiError> {
+    let deleted = delete(
+        grants_table
+            .filter(id_column.eq(id))
+            .filter(owner_id_column.eq(owner_id)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted == 1)
+}
+
+/// Updates the paths of the grants affected by moving `from` to `to`, like
+/// `db::file::record_file_move` does for tracked files.
+pub fn record_grant_move(
+    owner_id: i32,
+    from: &str,
+    to: &str,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let moved_grants = get_given_grants(owner_id, conn)?
+        .into_iter()
+        .filter_map(|grant| {
+            utils::moved_path(&grant.path, from, to).map(|path| (grant.id, grant.grantee_id, path))
+        })
+        .collect::<Vec<(i32, i32, String)>>();
+    let moved_ids = moved_grants
+        .iter()
+        .map(|(id, _, _)| *id)
+        .collect::<Vec<i32>>();
+
+    for (id, grantee_id, path) in &moved_grants {
+        delete(
+            grants_table
+                .filter(owner_id_column.eq(owner_id))
+                .filter(grantee_id_column.eq(grantee_id))
+                .filter(path_column.eq(path))
+                .filter(id_column.ne_all(&moved_ids)),
+        )
+        .execute(conn)?;
+        update(grants_table.filter(id_column.eq(id)))
+            .set(path_column.eq(path))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use crate::api_error::ApiError;
+use crate::db::last_insert_rowid;
+use crate::models::group::{Group, GroupMember, GroupRole, NewGroup};
+use crate::models::user::User;
+use crate::schema::group_members::group_id as member_group_id_column;
+use crate::schema::group_members::role as role_column;
+use crate::schema::group_members::table as group_members_table;
+use crate::schema::group_members::user_id as member_user_id_column;
+use crate::schema::groups::id as id_column;
+use crate::schema::groups::name as name_column;
+use crate::schema::groups::quota as quota_column;
+use crate::schema::groups::table as groups_table;
+use crate::schema::groups::used_bytes as used_bytes_column;
+use crate::schema::pending_uploads::quota_group_id as upload_group_id_column;
+use crate::schema::pending_uploads::table as pending_uploads_table;
+use crate::schema::users::table as users_table;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use diesel::{delete, insert_into, replace_into, update};
+
+/// Creates a group with the given user as its owner, returning the new group.
+pub fn create(group: &NewGroup, owner_id: i32, conn: &SqliteConnection) -> Result<Group, ApiError> {
+    let created = conn.transaction::<_, diesel::result::Error, _>(|| {
+        insert_into(groups_table).values(group).execute(conn)?;
+        let id = diesel::select(last_insert_rowid).get_result::<i64>(conn)?;
+        let created = groups_table
+            .filter(id_column.eq(id as i32))
+            .first::<Group>(conn)?;
+        insert_into(group_members_table)
+            .values(&GroupMember {
+                group_id: created.id,
+                user_id: owner_id,
+                role: GroupRole::Owner.as_str().to_string(),
+            })
+            .execute(conn)?;
+
+        Ok(created)
+    })?;
+
+    Ok(created)
+}
+
+pub fn get_group(id: i32, conn: &SqliteConnection) -> Result<Option<Group>, ApiError> {
+    let result = groups_table
+        .filter(id_column.eq(id))
+        .limit(1)
+        .load::<Group>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+pub fn update_group(
+    id: i32,
+    name: &str,
+    quota: Option<i64>,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    update(groups_table.filter(id_column.eq(id)))
+        .set((name_column.eq(name), quota_column.eq(quota)))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Deletes a group, along with its members and the uploads to it which are still waiting for
+/// their data.
+pub fn delete_group(id: i32, conn: &SqliteConnection) -> Result<(), ApiError> {
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        delete(pending_uploads_table.filter(upload_group_id_column.eq(id))).execute(conn)?;
+        delete(group_members_table.filter(member_group_id_column.eq(id))).execute(conn)?;
+        delete(groups_table.filter(id_column.eq(id))).execute(conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Gets every group, to count the space their files take.
+pub fn get_all(conn: &SqliteConnection) -> Result<Vec<Group>, ApiError> {
+    let result = groups_table.order(id_column.asc()).load::<Group>(conn)?;
+
+    Ok(result)
+}
+
+/// Like `db::user::add_used_bytes`, for the files of a group.
+pub fn add_used_bytes(id: i32, bytes: i64, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let group = groups_table.filter(id_column.eq(id));
+    let updated = if bytes <= 0 {
+        update(group)
+            .set(used_bytes_column.eq(used_bytes_column + bytes))
+            .execute(conn)?
+    } else {
+        update(
+            group.filter(
+                quota_column
+                    .is_null()
+                    .or(quota_column.ge((used_bytes_column + bytes).nullable())),
+            ),
+        )
+        .set(used_bytes_column.eq(used_bytes_column + bytes))
+        .execute(conn)?
+    };
+
+    Ok(updated == 1)
+}
+
+pub fn set_used_bytes(id: i32, bytes: i64, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(groups_table.filter(id_column.eq(id)))
+        .set(used_bytes_column.eq(bytes))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Gets the groups a user is a member of, along with their membership.
+pub fn get_user_groups(
+    user_id: i32,
+    conn: &SqliteConnection,
+) -> Result<Vec<(Group, GroupMember)>, ApiError> {
+    let result = groups_table
+        .inner_join(group_members_table)
+        .filter(member_user_id_column.eq(user_id))
+        .order(id_column.asc())
+        .load::<(Group, GroupMember)>(conn)?;
+
+    Ok(result)
+}
+
+pub fn get_membership(
+    group_id: i32,
+    user_id: i32,
+    conn: &SqliteConnection,
+) -> Result<Option<GroupMember>, ApiError> {
+    let result = group_members_table
+        .filter(member_group_id_column.eq(group_id))
+        .filter(member_user_id_column.eq(user_id))
+        .limit(1)
+        .load::<GroupMember>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+pub fn get_members(
+    group_id: i32,
+    conn: &SqliteConnection,
+) -> Result<Vec<(GroupMember, User)>, ApiError> {
+    let result = group_members_table
+        .inner_join(users_table)
+        .filter(member_group_id_column.eq(group_id))
+        .order(member_user_id_column.asc())
+        .load::<(GroupMember, User)>(conn)?;
+
+    Ok(result)
+}
+
+/// Adds a member to a group, or changes their role if they already are one.
+pub fn set_member(member: &GroupMember, conn: &SqliteConnection) -> Result<(), ApiError> {
+    replace_into(group_members_table)
+        .values(member)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Removes a member from a group, returning whether they were one.
+pub fn remove_member(
+    group_id: i32,
+    user_id: i32,
+    conn: &SqliteConnection,
+) -> Result<bool, ApiError> {
+    let deleted = delete(
+        group_members_table
+            .filter(member_group_id_column.eq(group_id))
+            .filter(member_user_id_column.eq(user_id)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted == 1)
+}
+
+pub fn count_owners(group_id: i32, conn: &SqliteConnection) -> Result<i64, ApiError> {
+    let count = group_members_table
+        .filter(member_group_id_column.eq(group_id))
+        .filter(role_column.eq(GroupRole::Owner.as_str()))
+        .count()
+        .get_result(conn)?;
+
+    Ok(count)
+}
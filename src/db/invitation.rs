@@ -0,0 +1,96 @@
+use crate::api_error::ApiError;
+use crate::models::invitation::{Invitation, NewInvitation};
+use crate::schema::invitations::code_hash as code_hash_column;
+use crate::schema::invitations::created_at as created_at_column;
+use crate::schema::invitations::created_by as created_by_column;
+use crate::schema::invitations::email as email_column;
+use crate::schema::invitations::expires_at as expires_at_column;
+use crate::schema::invitations::id as id_column;
+use crate::schema::invitations::max_uses as max_uses_column;
+use crate::schema::invitations::table as invitations_table;
+use crate::schema::invitations::uses as uses_column;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use diesel::{delete, insert_into, update};
+
+/// The columns an `Invitation` is loaded from.
+const INVITATION_COLUMNS: (
+    id_column,
+    created_by_column,
+    email_column,
+    max_uses_column,
+    uses_column,
+    created_at_column,
+    expires_at_column,
+) = (
+    id_column,
+    created_by_column,
+    email_column,
+    max_uses_column,
+    uses_column,
+    created_at_column,
+    expires_at_column,
+);
+
+/// Creates an invitation, returning it once stored.
+pub fn create(invitation: &NewInvitation, conn: &SqliteConnection) -> Result<Invitation, ApiError> {
+    let created = conn.transaction::<_, diesel::result::Error, _>(|| {
+        insert_into(invitations_table)
+            .values(invitation)
+            .execute(conn)?;
+        invitations_table
+            .filter(code_hash_column.eq(&invitation.code_hash))
+            .select(INVITATION_COLUMNS)
+            .first::<Invitation>(conn)
+    })?;
+
+    Ok(created)
+}
+
+pub fn get_by_hash(
+    code_hash: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<Invitation>, ApiError> {
+    let result = invitations_table
+        .filter(code_hash_column.eq(code_hash))
+        .select(INVITATION_COLUMNS)
+        .limit(1)
+        .load::<Invitation>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+/// Gets every invitation, most recent first.
+pub fn get_all(conn: &SqliteConnection) -> Result<Vec<Invitation>, ApiError> {
+    let result = invitations_table
+        .order(created_at_column.desc())
+        .select(INVITATION_COLUMNS)
+        .load::<Invitation>(conn)?;
+
+    Ok(result)
+}
+
+/// Counts one more use of an invitation, returning whether it could still be used.
+///
+/// This is done in a single statement so that concurrent registrations cannot use an
+/// invitation more times than it allows.
+pub fn use_invitation(id: i32, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let updated = update(
+        invitations_table
+            .filter(id_column.eq(id))
+            .filter(uses_column.lt(max_uses_column)),
+    )
+    .set(uses_column.eq(uses_column + 1))
+    .execute(conn)?;
+
+    Ok(updated > 0)
+}
+
+/// Deletes an invitation, returning whether it existed.
+pub fn delete_invitation(id: i32, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let deleted = delete(invitations_table.filter(id_column.eq(id))).execute(conn)?;
+
+    Ok(deleted > 0)
+}
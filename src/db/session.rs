@@ -0,0 +1,110 @@
+use crate::api_error::ApiError;
+use crate::models::user::{ActiveSession, User};
+use crate::schema::sessions::expires_at as expires_at_column;
+use crate::schema::sessions::id as id_column;
+use crate::schema::sessions::idle_timeout as idle_timeout_column;
+use crate::schema::sessions::ip as ip_column;
+use crate::schema::sessions::last_seen as last_seen_column;
+use crate::schema::sessions::table as sessions_table;
+use crate::schema::sessions::user_id as user_id_column;
+use crate::schema::users::table as users_table;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use diesel::{delete, insert_into, update};
+
+pub fn create(session: &ActiveSession, conn: &SqliteConnection) -> Result<(), ApiError> {
+    insert_into(sessions_table).values(session).execute(conn)?;
+
+    Ok(())
+}
+
+/// Gets a session from the hash of its key, along with the user it belongs to.
+pub fn get_session(
+    key: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<(ActiveSession, User)>, ApiError> {
+    let result = sessions_table
+        .inner_join(users_table)
+        .filter(id_column.eq(key))
+        .limit(1)
+        .load::<(ActiveSession, User)>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+/// Gets the sessions of a user, most recently used first.
+pub fn get_user_sessions(
+    user_id: i32,
+    conn: &SqliteConnection,
+) -> Result<Vec<ActiveSession>, ApiError> {
+    let result = sessions_table
+        .filter(user_id_column.eq(user_id))
+        .order(last_seen_column.desc())
+        .load::<ActiveSession>(conn)?;
+
+    Ok(result)
+}
+
+/// Records that a session was just used, from the given IP address if it is known.
+pub fn record_session_activity(
+    key: &str,
+    last_seen: i64,
+    ip: Option<String>,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let session = sessions_table.filter(id_column.eq(key));
+    match ip {
+        Some(ip) => update(session)
+            .set((last_seen_column.eq(last_seen), ip_column.eq(ip)))
+            .execute(conn)?,
+        None => update(session)
+            .set(last_seen_column.eq(last_seen))
+            .execute(conn)?,
+    };
+
+    Ok(())
+}
+
+pub fn delete_session(key: &str, conn: &SqliteConnection) -> Result<(), ApiError> {
+    delete(sessions_table.filter(id_column.eq(key))).execute(conn)?;
+
+    Ok(())
+}
+
+pub fn delete_user_sessions(user_id: i32, conn: &SqliteConnection) -> Result<usize, ApiError> {
+    let deleted = delete(sessions_table.filter(user_id_column.eq(user_id))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+/// Deletes all the sessions of a user except the given one.
+pub fn delete_other_sessions(
+    user_id: i32,
+    key: &str,
+    conn: &SqliteConnection,
+) -> Result<usize, ApiError> {
+    let deleted = delete(
+        sessions_table
+            .filter(user_id_column.eq(user_id))
+            .filter(id_column.ne(key)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted)
+}
+
+/// Deletes the sessions that expired, either because they are too old or were not used for too long.
+pub fn delete_expired_sessions(now: i64, conn: &SqliteConnection) -> Result<usize, ApiError> {
+    let deleted = delete(
+        sessions_table.filter(
+            expires_at_column
+                .le(now)
+                .or((last_seen_column.nullable() + idle_timeout_column).le(now)),
+        ),
+    )
+    .execute(conn)?;
+
+    Ok(deleted)
+}
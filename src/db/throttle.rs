@@ -0,0 +1,124 @@
+use crate::api_error::ApiError;
+use crate::models::user::LoginThrottle;
+use crate::schema::login_throttles::kind as kind_column;
+use crate::schema::login_throttles::last_failure as last_failure_column;
+use crate::schema::login_throttles::locked_until as locked_until_column;
+use crate::schema::login_throttles::table as login_throttles_table;
+use crate::schema::login_throttles::value as value_column;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
+use diesel::SqliteConnection;
+use diesel::{delete, sql_query, update};
+
+pub fn get(
+    kind: &str,
+    value: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<LoginThrottle>, ApiError> {
+    let result = login_throttles_table
+        .filter(kind_column.eq(kind))
+        .filter(value_column.eq(value))
+        .limit(1)
+        .load::<LoginThrottle>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+/// Counts one more failed login attempt for an account or client address, returning the number
+/// of failures, which starts over when the previous one happened before `window_start`.
+///
+/// The count is incremented in a single statement so that concurrent attempts are all counted.
+pub fn record_failure(
+    kind: &str,
+    value: &str,
+    now: i64,
+    window_start: i64,
+    conn: &SqliteConnection,
+) -> Result<i32, ApiError> {
+    let throttle = conn.transaction::<_, diesel::result::Error, _>(|| {
+        sql_query(
+            "INSERT INTO login_throttles (kind, value, failures, last_failure) VALUES (?, ?, 1, ?)
+             ON CONFLICT (kind, value) DO UPDATE SET
+                 failures = CASE WHEN last_failure > ?
+                     THEN MIN(failures + 1, 2147483647) ELSE 1 END,
+                 last_failure = excluded.last_failure",
+        )
+        .bind::<Text, _>(kind)
+        .bind::<Text, _>(value)
+        .bind::<BigInt, _>(now)
+        .bind::<BigInt, _>(window_start)
+        .execute(conn)?;
+
+        login_throttles_table
+            .filter(kind_column.eq(kind))
+            .filter(value_column.eq(value))
+            .first::<LoginThrottle>(conn)
+    })?;
+
+    Ok(throttle.failures)
+}
+
+/// Refuses logins for an account or client address until the given time.
+pub fn lock(
+    kind: &str,
+    value: &str,
+    locked_until: i64,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    update(
+        login_throttles_table
+            .filter(kind_column.eq(kind))
+            .filter(value_column.eq(value)),
+    )
+    .set(locked_until_column.eq(locked_until))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+/// Forgets the failed login attempts of an account or client address, returning whether there
+/// were any.
+pub fn delete_throttle(kind: &str, value: &str, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let deleted = delete(
+        login_throttles_table
+            .filter(kind_column.eq(kind))
+            .filter(value_column.eq(value)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted > 0)
+}
+
+/// Gets the accounts and client addresses which cannot login at the given time, the ones
+/// locked the longest first.
+pub fn get_locked(now: i64, conn: &SqliteConnection) -> Result<Vec<LoginThrottle>, ApiError> {
+    let result = login_throttles_table
+        .filter(locked_until_column.gt(now))
+        .order(locked_until_column.desc())
+        .load::<LoginThrottle>(conn)?;
+
+    Ok(result)
+}
+
+/// Forgets failed login attempts which happened before the given time, unless they still
+/// prevent logins.
+pub fn delete_old_throttles(
+    before: i64,
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<usize, ApiError> {
+    let deleted = delete(
+        login_throttles_table
+            .filter(last_failure_column.lt(before))
+            .filter(
+                locked_until_column
+                    .is_null()
+                    .or(locked_until_column.le(now)),
+            ),
+    )
+    .execute(conn)?;
+
+    Ok(deleted)
+}
@@ -0,0 +1,102 @@
+use crate::api_error::ApiError;
+use crate::models::token::{ApiToken, NewApiToken};
+use crate::models::user::User;
+use crate::schema::api_tokens::created_at as created_at_column;
+use crate::schema::api_tokens::expires_at as expires_at_column;
+use crate::schema::api_tokens::id as id_column;
+use crate::schema::api_tokens::last_used_at as last_used_at_column;
+use crate::schema::api_tokens::name as name_column;
+use crate::schema::api_tokens::scopes as scopes_column;
+use crate::schema::api_tokens::table as api_tokens_table;
+use crate::schema::api_tokens::token_hash as token_hash_column;
+use crate::schema::api_tokens::user_id as user_id_column;
+use crate::schema::users::all_columns as user_columns;
+use crate::schema::users::table as users_table;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use diesel::{delete, insert_into, update};
+
+/// The columns an `ApiToken` is loaded from.
+const TOKEN_COLUMNS: (
+    id_column,
+    name_column,
+    scopes_column,
+    created_at_column,
+    expires_at_column,
+    last_used_at_column,
+) = (
+    id_column,
+    name_column,
+    scopes_column,
+    created_at_column,
+    expires_at_column,
+    last_used_at_column,
+);
+
+/// Creates an API token, returning it once stored.
+pub fn create(token: &NewApiToken, conn: &SqliteConnection) -> Result<ApiToken, ApiError> {
+    let created = conn.transaction::<_, diesel::result::Error, _>(|| {
+        insert_into(api_tokens_table).values(token).execute(conn)?;
+        api_tokens_table
+            .filter(token_hash_column.eq(&token.token_hash))
+            .select(TOKEN_COLUMNS)
+            .first::<ApiToken>(conn)
+    })?;
+
+    Ok(created)
+}
+
+/// Gets a token from its hash, along with the user it belongs to.
+pub fn get_by_hash(
+    token_hash: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<(ApiToken, User)>, ApiError> {
+    let result = api_tokens_table
+        .inner_join(users_table)
+        .filter(token_hash_column.eq(token_hash))
+        .select((TOKEN_COLUMNS, user_columns))
+        .limit(1)
+        .load::<(ApiToken, User)>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+/// Gets the tokens of a user, most recent first.
+pub fn get_user_tokens(user_id: i32, conn: &SqliteConnection) -> Result<Vec<ApiToken>, ApiError> {
+    let result = api_tokens_table
+        .filter(user_id_column.eq(user_id))
+        .order(created_at_column.desc())
+        .select(TOKEN_COLUMNS)
+        .load::<ApiToken>(conn)?;
+
+    Ok(result)
+}
+
+pub fn record_token_use(id: i32, used_at: i64, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(api_tokens_table.filter(id_column.eq(id)))
+        .set(last_used_at_column.eq(used_at))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Deletes a token of the given user, returning whether it existed.
+pub fn delete_user_token(id: i32, user_id: i32, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let deleted = delete(
+        api_tokens_table
+            .filter(id_column.eq(id))
+            .filter(user_id_column.eq(user_id)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted > 0)
+}
+
+/// Deletes all the tokens of a user, returning how many there were.
+pub fn delete_user_tokens(user_id: i32, conn: &SqliteConnection) -> Result<usize, ApiError> {
+    let deleted = delete(api_tokens_table.filter(user_id_column.eq(user_id))).execute(conn)?;
+
+    Ok(deleted)
+}
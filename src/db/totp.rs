@@ -0,0 +1,113 @@
+use crate::api_error::ApiError;
+use crate::models::totp::{NewRecoveryCode, TotpSecret};
+use crate::schema::recovery_codes::code_hash as code_hash_column;
+use crate::schema::recovery_codes::table as recovery_codes_table;
+use crate::schema::recovery_codes::user_id as code_user_id_column;
+use crate::schema::totp_secrets::enabled as enabled_column;
+use crate::schema::totp_secrets::last_used_step as last_used_step_column;
+use crate::schema::totp_secrets::table as totp_secrets_table;
+use crate::schema::totp_secrets::user_id as user_id_column;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use diesel::{delete, insert_into, replace_into, update};
+
+pub fn get_secret(user_id: i32, conn: &SqliteConnection) -> Result<Option<TotpSecret>, ApiError> {
+    let result = totp_secrets_table
+        .filter(user_id_column.eq(user_id))
+        .limit(1)
+        .load::<TotpSecret>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+/// Saves the secret of an enrollment which still needs to be confirmed, replacing any previous one.
+pub fn save_secret(secret: &TotpSecret, conn: &SqliteConnection) -> Result<(), ApiError> {
+    replace_into(totp_secrets_table)
+        .values(secret)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Enables two-factor authentication for a user, replacing their recovery codes with the given ones.
+pub fn enable(
+    user_id: i32,
+    used_step: i64,
+    codes: &[NewRecoveryCode],
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        update(totp_secrets_table.filter(user_id_column.eq(user_id)))
+            .set((enabled_column.eq(true), last_used_step_column.eq(used_step)))
+            .execute(conn)?;
+        delete(recovery_codes_table.filter(code_user_id_column.eq(user_id))).execute(conn)?;
+        insert_into(recovery_codes_table)
+            .values(codes)
+            .execute(conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Records that the code of a time step was used, returning false if a code of the same or
+/// a later step was already accepted.
+pub fn record_used_step(
+    user_id: i32,
+    step: i64,
+    conn: &SqliteConnection,
+) -> Result<bool, ApiError> {
+    let updated = update(
+        totp_secrets_table
+            .filter(user_id_column.eq(user_id))
+            .filter(
+                last_used_step_column
+                    .is_null()
+                    .or(last_used_step_column.lt(step)),
+            ),
+    )
+    .set(last_used_step_column.eq(step))
+    .execute(conn)?;
+
+    Ok(updated > 0)
+}
+
+/// Consumes a recovery code, returning whether it was valid.
+pub fn use_recovery_code(
+    user_id: i32,
+    code_hash: &str,
+    conn: &SqliteConnection,
+) -> Result<bool, ApiError> {
+    let deleted = delete(
+        recovery_codes_table
+            .filter(code_user_id_column.eq(user_id))
+            .filter(code_hash_column.eq(code_hash)),
+    )
+    .execute(conn)?;
+
+    Ok(deleted > 0)
+}
+
+pub fn count_recovery_codes(user_id: i32, conn: &SqliteConnection) -> Result<i64, ApiError> {
+    let count = recovery_codes_table
+        .filter(code_user_id_column.eq(user_id))
+        .count()
+        .get_result(conn)?;
+
+    Ok(count)
+}
+
+/// Disables two-factor authentication for a user, deleting their secret and recovery codes.
+pub fn disable(user_id: i32, conn: &SqliteConnection) -> Result<(), ApiError> {
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        delete(recovery_codes_table.filter(code_user_id_column.eq(user_id))).execute(conn)?;
+        delete(totp_secrets_table.filter(user_id_column.eq(user_id))).execute(conn)?;
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
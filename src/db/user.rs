@@ -1,10 +1,28 @@
 use crate::api_error::{ApiError, CustomError};
-use crate::models::user::{User, UserCreate};
+use crate::models::user::{EmailVerification, PasswordResetToken, User, UserCreate};
+use crate::schema::email_verifications::expires_at as verification_expires_at_column;
+use crate::schema::email_verifications::table as email_verifications_table;
+use crate::schema::email_verifications::token_hash as verification_token_hash_column;
+use crate::schema::email_verifications::user_id as verification_user_id_column;
+use crate::schema::password_resets::created_at as reset_created_at_column;
+use crate::schema::password_resets::expires_at as reset_expires_at_column;
+use crate::schema::password_resets::table as password_resets_table;
+use crate::schema::password_resets::token_hash as reset_token_hash_column;
+use crate::schema::password_resets::user_id as reset_user_id_column;
+use crate::schema::users::disabled as disabled_column;
+use crate::schema::users::display_name as display_name_column;
 use crate::schema::users::email as email_column;
+use crate::schema::users::email_verified as email_verified_column;
+use crate::schema::users::id as id_column;
+use crate::schema::users::password as password_column;
+use crate::schema::users::quota as quota_column;
+use crate::schema::users::role as role_column;
 use crate::schema::users::table as users_table;
-use diesel::insert_into;
+use crate::schema::users::used_bytes as used_bytes_column;
 use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
 use diesel::SqliteConnection;
+use diesel::{delete, insert_into, update};
 use rocket::http::Status;
 
 pub fn create(user: &UserCreate, conn: &SqliteConnection) -> Result<(), ApiError> {
@@ -26,3 +44,248 @@ pub fn get_by_email(email: &str, conn: &SqliteConnection) -> Result<Option<User>
 
     Ok(result)
 }
+
+pub fn get_by_id(id: i32, conn: &SqliteConnection) -> Result<Option<User>, ApiError> {
+    let result = users_table
+        .filter(id_column.eq(id))
+        .limit(1)
+        .load::<User>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+pub fn update_password(
+    id: i32,
+    password_hash: &str,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set(password_column.eq(password_hash))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Changes the email address of a user, which then has to be verified again.
+pub fn update_email(id: i32, email: &str, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set((email_column.eq(email), email_verified_column.eq(false)))
+        .execute(conn)
+        .map_err(|e| match e {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => CustomError::new(
+                "This email address is already used".to_string(),
+                Status::Conflict,
+            ),
+            e => CustomError::new(e.to_string(), Status::InternalServerError),
+        })?;
+
+    Ok(())
+}
+
+pub fn update_display_name(
+    id: i32,
+    display_name: &str,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set(display_name_column.eq(display_name))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn set_email_verified(id: i32, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set(email_verified_column.eq(true))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Gets every user, in the order they registered.
+pub fn get_all(conn: &SqliteConnection) -> Result<Vec<User>, ApiError> {
+    let result = users_table.order(id_column.asc()).load::<User>(conn)?;
+
+    Ok(result)
+}
+
+pub fn set_role(id: i32, role: &str, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set(role_column.eq(role))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn set_disabled(id: i32, disabled: bool, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set(disabled_column.eq(disabled))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn set_quota(id: i32, quota: Option<i64>, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set(quota_column.eq(quota))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Adds to the number of bytes the files of a user take, unless that would go over their
+/// quota. The quota is checked in the same statement as the update, so concurrent uploads
+/// cannot go over it. Returns whether the bytes were added, which they always are when they
+/// were freed (negative).
+pub fn add_used_bytes(id: i32, bytes: i64, conn: &SqliteConnection) -> Result<bool, ApiError> {
+    let user = users_table.filter(id_column.eq(id));
+    let updated = if bytes <= 0 {
+        update(user)
+            .set(used_bytes_column.eq(used_bytes_column + bytes))
+            .execute(conn)?
+    } else {
+        update(
+            user.filter(
+                quota_column
+                    .is_null()
+                    .or(quota_column.ge((used_bytes_column + bytes).nullable())),
+            ),
+        )
+        .set(used_bytes_column.eq(used_bytes_column + bytes))
+        .execute(conn)?
+    };
+
+    Ok(updated == 1)
+}
+
+pub fn set_used_bytes(id: i32, bytes: i64, conn: &SqliteConnection) -> Result<(), ApiError> {
+    update(users_table.filter(id_column.eq(id)))
+        .set(used_bytes_column.eq(bytes))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+pub fn create_password_reset(
+    reset: &PasswordResetToken,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    insert_into(password_resets_table)
+        .values(reset)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Counts the password reset tokens created for a user since the given time.
+pub fn count_password_resets_since(
+    user_id: i32,
+    since: i64,
+    conn: &SqliteConnection,
+) -> Result<i64, ApiError> {
+    let count = password_resets_table
+        .filter(reset_user_id_column.eq(user_id))
+        .filter(reset_created_at_column.ge(since))
+        .count()
+        .get_result(conn)?;
+
+    Ok(count)
+}
+
+pub fn get_password_reset(
+    token_hash: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<PasswordResetToken>, ApiError> {
+    let result = password_resets_table
+        .filter(reset_token_hash_column.eq(token_hash))
+        .limit(1)
+        .load::<PasswordResetToken>(conn)?
+        .into_iter()
+        .next();
+
+    Ok(result)
+}
+
+/// Consumes a password reset token, returning it if it exists. Every other token of the same
+/// user is deleted as well, so that none of them can be used once the password was reset.
+pub fn take_password_reset(
+    token_hash: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<PasswordResetToken>, ApiError> {
+    let reset = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let reset = password_resets_table
+            .filter(reset_token_hash_column.eq(token_hash))
+            .limit(1)
+            .load::<PasswordResetToken>(conn)?
+            .into_iter()
+            .next();
+        if let Some(reset) = &reset {
+            delete(password_resets_table.filter(reset_user_id_column.eq(reset.user_id)))
+                .execute(conn)?;
+        }
+
+        Ok(reset)
+    })?;
+
+    Ok(reset)
+}
+
+pub fn delete_expired_password_resets(
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<usize, ApiError> {
+    let deleted =
+        delete(password_resets_table.filter(reset_expires_at_column.le(now))).execute(conn)?;
+
+    Ok(deleted)
+}
+
+pub fn create_email_verification(
+    verification: &EmailVerification,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    insert_into(email_verifications_table)
+        .values(verification)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Consumes an email verification token, returning it if it exists. Every other token of the
+/// same user is deleted as well, as they are not needed anymore.
+pub fn take_email_verification(
+    token_hash: &str,
+    conn: &SqliteConnection,
+) -> Result<Option<EmailVerification>, ApiError> {
+    let verification = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let verification = email_verifications_table
+            .filter(verification_token_hash_column.eq(token_hash))
+            .limit(1)
+            .load::<EmailVerification>(conn)?
+            .into_iter()
+            .next();
+        if let Some(verification) = &verification {
+            delete(
+                email_verifications_table
+                    .filter(verification_user_id_column.eq(verification.user_id)),
+            )
+            .execute(conn)?;
+        }
+
+        Ok(verification)
+    })?;
+
+    Ok(verification)
+}
+
+pub fn delete_expired_email_verifications(
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<usize, ApiError> {
+    let deleted = delete(email_verifications_table.filter(verification_expires_at_column.le(now)))
+        .execute(conn)?;
+
+    Ok(deleted)
+}
@@ -0,0 +1,61 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::mailer::{self, Email};
+use crate::models::user::{EmailVerification, User};
+use crate::tokens;
+use crate::utils;
+use crate::MailQueue;
+use diesel::SqliteConnection;
+use rocket::http::Status;
+
+/// Number of seconds during which an email verification token can be used.
+pub const EMAIL_VERIFICATION_LIFETIME: i64 = 24 * 60 * 60;
+
+/// Creates a token proving that the user owns their email address, and emails it to them.
+pub fn send_verification_email(
+    user: &User,
+    mail_queue: &MailQueue,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let token = tokens::random_string()?;
+    let now = utils::unix_timestamp();
+    db::user::create_email_verification(
+        &EmailVerification {
+            token_hash: tokens::hash_token(&token),
+            user_id: user.id,
+            email: user.email.to_string(),
+            created_at: now,
+            expires_at: now + EMAIL_VERIFICATION_LIFETIME,
+        },
+        conn,
+    )?;
+    mailer::enqueue(
+        mail_queue,
+        Email {
+            to: user.email.to_string(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Hello {},\n\n\
+                 Please use this token within the next day to verify the email address of \
+                 your account:\n\n{}\n\n\
+                 If you did not create an account, you can ignore this email.\n",
+                user.display_name, token
+            ),
+        },
+    );
+
+    Ok(())
+}
+
+/// Makes sure that a user verified their email address when `REQUIRE_EMAIL_VERIFICATION` is
+/// set, before they upload files.
+pub fn ensure_verified(user: &User) -> Result<(), ApiError> {
+    if utils::email_verification_required() && !user.email_verified {
+        Err(CustomError::new(
+            "Please verify your email address before uploading files".to_string(),
+            Status::Forbidden,
+        ))?;
+    }
+
+    Ok(())
+}
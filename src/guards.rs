@@ -1,21 +1,134 @@
-use crate::models::user::User;
-use crate::schema::users;
+use crate::db;
+use crate::models::user::{Role, User};
+use crate::thumbnails::ThumbnailFormat;
+use crate::tokens;
+use crate::utils::{self, unix_timestamp};
 use crate::DBConnection;
-use crate::SessionStore;
-use diesel::prelude::*;
 use rocket::http::Status;
 use rocket::request::{self, FromRequest, Request};
 use rocket::Outcome;
-use rocket::State;
+use std::env;
+use std::net::IpAddr;
 use uuid::Uuid;
 
+/// The representation of a resource preferred by a client, according to its `Accept` header.
+#[derive(Debug, PartialEq)]
+pub enum ResponseFormat {
+    Html,
+    Json,
+    Any,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ResponseFormat {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let format = match request.accept().map(|accept| accept.preferred().media_type()) {
+            Some(media_type) if media_type.is_html() => ResponseFormat::Html,
+            Some(media_type) if media_type.is_json() => ResponseFormat::Json,
+            _ => ResponseFormat::Any,
+        };
+
+        Outcome::Success(format)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ThumbnailFormat {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let accepts_webp = request.accept().map_or(false, |accept| {
+            accept
+                .iter()
+                .any(|media_type| media_type.top() == "image" && media_type.sub() == "webp")
+        });
+
+        if accepts_webp {
+            Outcome::Success(ThumbnailFormat::WebP)
+        } else {
+            Outcome::Success(ThumbnailFormat::Jpeg)
+        }
+    }
+}
+
+/// Returns the address of the client sending a request.
+///
+/// The `X-Real-IP` header is only used when the request comes from a trusted reverse proxy, see
+/// `utils::is_trusted_proxy`, since clients could send any address in it otherwise.
+fn client_ip(request: &Request) -> Option<IpAddr> {
+    let remote = request.remote()?.ip();
+    if utils::is_trusted_proxy(remote) {
+        request.real_ip().or(Some(remote))
+    } else {
+        Some(remote)
+    }
+}
+
+/// Information about the client sending a request, as recorded in the access log of shares.
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            ip: client_ip(request),
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .map(|agent| agent.to_string()),
+        })
+    }
+}
+
+/// The URL at which clients reach the server, which is used to build links to it.
+///
+/// This is `PUBLIC_URL`, since the `Host` header is chosen by clients and cannot be trusted. The
+/// routes which need it fail when it is not set.
+pub struct BaseUrl(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for BaseUrl {
+    type Error = ();
+
+    fn from_request(_request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        match env::var("PUBLIC_URL") {
+            Ok(public_url) => {
+                Outcome::Success(BaseUrl(public_url.trim_end_matches('/').to_string()))
+            }
+            Err(_) => Outcome::Failure((Status::InternalServerError, ())),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum AuthenticationError {
     Unauthenticated,
+    /// An API token was used for something its scopes do not allow, a user who is not an
+    /// administrator tried to use an administration route, or the account was disabled
+    Forbidden,
     ServerError
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for User {
+/// Number of seconds after which the last activity of a session is recorded again.
+const SESSION_ACTIVITY_INTERVAL: i64 = 60;
+
+/// The key of the session a request was sent with, read from its private `session` cookie.
+///
+/// This does not check that the session is still active, which the `User` guard does.
+pub struct SessionId(pub Uuid);
+
+impl SessionId {
+    /// Returns the key under which the session is stored, which is a hash of the one in the
+    /// cookie so that a leaked database cannot be used to impersonate users.
+    pub fn key(&self) -> String {
+        tokens::hash_token(&self.0.to_string())
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for SessionId {
     type Error = AuthenticationError;
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
@@ -28,19 +141,31 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
                 ))
             }
         };
-        let cookie_value = cookie.value();
-        let session_id = match Uuid::parse_str(cookie_value) {
-            Ok(uuid) => uuid,
+        match Uuid::parse_str(cookie.value()) {
+            Ok(uuid) => Outcome::Success(SessionId(uuid)),
             Err(_) => {
-                return Outcome::Failure((
-                    Status::Unauthorized,
-                    AuthenticationError::Unauthenticated,
-                ))
+                Outcome::Failure((Status::Unauthorized, AuthenticationError::Unauthenticated))
             }
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for User {
+    type Error = AuthenticationError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        if let Some(authorization) = request.headers().get_one("Authorization") {
+            return authenticate_with_token(request, authorization);
+        }
+
+        let session_id = match request.guard::<SessionId>() {
+            Outcome::Success(session_id) => session_id,
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(()) => return Outcome::Forward(()),
         };
 
-        let session_store = match request.guard::<State<SessionStore>>() {
-            Outcome::Success(session_store) => session_store,
+        let conn = match request.guard::<DBConnection>() {
+            Outcome::Success(conn) => conn,
             _ => {
                 return Outcome::Failure((
                     Status::InternalServerError,
@@ -48,32 +173,23 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
                 ))
             }
         };
-        let active_session_ids = session_store.read();
-        let user_email = match active_session_ids.get(&session_id) {
-            Some(session) => session.email.to_string(),
-            None => {
+        let key = session_id.key();
+        let now = unix_timestamp();
+        let (session, db_user) = match db::session::get_session(&key, &conn) {
+            Ok(Some((session, user))) if !session.is_expired(now) => (session, user),
+            Ok(Some(_)) => {
+                let _ = db::session::delete_session(&key, &conn);
                 return Outcome::Failure((
                     Status::Unauthorized,
                     AuthenticationError::Unauthenticated,
-                ))
+                ));
             }
-        };
-
-        let conn = match request.guard::<DBConnection>() {
-            Outcome::Success(conn) => conn,
-            _ => {
+            Ok(None) => {
                 return Outcome::Failure((
-                    Status::InternalServerError,
-                    AuthenticationError::ServerError,
+                    Status::Unauthorized,
+                    AuthenticationError::Unauthenticated,
                 ))
             }
-        };
-        let query_result = match users::table
-            .filter(users::email.eq(user_email))
-            .limit(1)
-            .load::<User>(&*conn)
-        {
-            Ok(result) => result,
             Err(_) => {
                 return Outcome::Failure((
                     Status::InternalServerError,
@@ -81,16 +197,98 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
                 ))
             }
         };
-        let db_user = match query_result.into_iter().next() {
-            Some(user) => user,
-            None => {
+        if db_user.disabled {
+            return Outcome::Failure((Status::Forbidden, AuthenticationError::Forbidden));
+        }
+        // Avoid writing to the database on every request
+        if now - session.last_seen >= SESSION_ACTIVITY_INTERVAL {
+            let ip = client_ip(request).map(|ip| ip.to_string());
+            if db::session::record_session_activity(&key, now, ip, &conn).is_err() {
                 return Outcome::Failure((
-                    Status::Unauthorized,
-                    AuthenticationError::Unauthenticated,
-                ))
+                    Status::InternalServerError,
+                    AuthenticationError::ServerError,
+                ));
             }
-        };
+        }
 
         Outcome::Success(db_user)
     }
 }
+
+/// A user allowed to administrate the server, see `User::role`.
+pub struct Admin(pub User);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = AuthenticationError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let user = match request.guard::<User>() {
+            Outcome::Success(user) => user,
+            Outcome::Failure(failure) => return Outcome::Failure(failure),
+            Outcome::Forward(()) => return Outcome::Forward(()),
+        };
+
+        if user.role() == Role::Admin {
+            Outcome::Success(Admin(user))
+        } else {
+            Outcome::Failure((Status::Forbidden, AuthenticationError::Forbidden))
+        }
+    }
+}
+
+/// Authenticates a request sent with an `Authorization: Bearer` header containing an API token,
+/// which is only accepted by the routes its scopes allow.
+fn authenticate_with_token(
+    request: &Request,
+    authorization: &str,
+) -> request::Outcome<User, AuthenticationError> {
+    let token = match authorization.strip_prefix("Bearer ") {
+        Some(token) => token.trim(),
+        None => {
+            return Outcome::Failure((Status::Unauthorized, AuthenticationError::Unauthenticated))
+        }
+    };
+
+    let conn = match request.guard::<DBConnection>() {
+        Outcome::Success(conn) => conn,
+        _ => {
+            return Outcome::Failure((
+                Status::InternalServerError,
+                AuthenticationError::ServerError,
+            ))
+        }
+    };
+    let now = unix_timestamp();
+    let (api_token, db_user) = match db::token::get_by_hash(&tokens::hash_token(token), &conn) {
+        Ok(Some((api_token, user))) if !api_token.is_expired(now) => (api_token, user),
+        Ok(_) => {
+            return Outcome::Failure((Status::Unauthorized, AuthenticationError::Unauthenticated))
+        }
+        Err(_) => {
+            return Outcome::Failure((
+                Status::InternalServerError,
+                AuthenticationError::ServerError,
+            ))
+        }
+    };
+
+    if db_user.disabled {
+        return Outcome::Failure((Status::Forbidden, AuthenticationError::Forbidden));
+    }
+    let allowed = request
+        .route()
+        .and_then(tokens::required_scope)
+        .map_or(false, |scope| api_token.scopes().contains(&scope));
+    if !allowed {
+        return Outcome::Failure((Status::Forbidden, AuthenticationError::Forbidden));
+    }
+
+    if db::token::record_token_use(api_token.id, now, &conn).is_err() {
+        return Outcome::Failure((
+            Status::InternalServerError,
+            AuthenticationError::ServerError,
+        ));
+    }
+
+    Outcome::Success(db_user)
+}
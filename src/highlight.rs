@@ -0,0 +1,338 @@
+/// The syntax of a language, as far as the highlighter is concerned.
+struct Language {
+    keywords: &'static [&'static str],
+    case_insensitive: bool,
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+}
+
+impl Language {
+    fn is_plain(&self) -> bool {
+        self.keywords.is_empty() && self.line_comment.is_none() && self.block_comment.is_none()
+    }
+}
+
+const PLAIN: Language = Language {
+    keywords: &[],
+    case_insensitive: false,
+    line_comment: None,
+    block_comment: None,
+};
+
+const TEXT_EXTENSIONS: [&str; 44] = [
+    "bash", "c", "cc", "cfg", "conf", "cpp", "cs", "css", "csv", "go", "h", "hpp", "ini", "java",
+    "js", "json", "jsx", "kt", "log", "lua", "md", "mjs", "php", "pl", "py", "rb", "rs", "scss",
+    "sh", "sql", "swift", "toml", "ts", "tsv", "tsx", "txt", "vue", "xml", "yaml", "yml", "zsh",
+    "dockerfile", "makefile", "gitignore",
+];
+
+fn language_for(extension: &str) -> Language {
+    match extension {
+        "rs" => Language {
+            keywords: &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+                "enum", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+                "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct",
+                "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+            ],
+            case_insensitive: false,
+            line_comment: Some("//"),
+            block_comment: Some(("/*", "*/")),
+        },
+        "py" => Language {
+            keywords: &[
+                "and", "as", "async", "await", "break", "class", "continue", "def", "elif",
+                "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+                "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+                "True", "try", "while", "with", "yield",
+            ],
+            case_insensitive: false,
+            line_comment: Some("#"),
+            block_comment: None,
+        },
+        "js" | "jsx" | "mjs" | "ts" | "tsx" | "vue" => Language {
+            keywords: &[
+                "async", "await", "break", "case", "catch", "class", "const", "continue",
+                "default", "do", "else", "export", "extends", "false", "finally", "for", "from",
+                "function", "if", "import", "instanceof", "interface", "let", "new", "null",
+                "return", "switch", "this", "throw", "true", "try", "type", "typeof",
+                "undefined", "var", "while", "yield",
+            ],
+            case_insensitive: false,
+            line_comment: Some("//"),
+            block_comment: Some(("/*", "*/")),
+        },
+        "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "java" | "kt" | "swift" | "php" => Language {
+            keywords: &[
+                "break", "case", "char", "class", "const", "continue", "default", "delete",
+                "do", "double", "else", "enum", "extends", "false", "final", "float", "for",
+                "if", "implements", "import", "include", "int", "interface", "long",
+                "namespace", "new", "null", "nullptr", "package", "private", "protected",
+                "public", "return", "short", "sizeof", "static", "struct", "switch", "template",
+                "this", "throw", "throws", "true", "try", "typedef", "union", "unsigned", "void",
+                "while",
+            ],
+            case_insensitive: false,
+            line_comment: Some("//"),
+            block_comment: Some(("/*", "*/")),
+        },
+        "go" => Language {
+            keywords: &[
+                "break", "case", "chan", "const", "continue", "defer", "else", "false", "for",
+                "func", "go", "if", "import", "interface", "map", "nil", "package", "range",
+                "return", "select", "struct", "switch", "true", "type", "var",
+            ],
+            case_insensitive: false,
+            line_comment: Some("//"),
+            block_comment: Some(("/*", "*/")),
+        },
+        "sh" | "bash" | "zsh" | "dockerfile" | "makefile" | "pl" | "rb" => Language {
+            keywords: &[
+                "case", "def", "do", "done", "elif", "else", "end", "esac", "export", "fi",
+                "for", "function", "if", "in", "local", "return", "then", "while",
+            ],
+            case_insensitive: false,
+            line_comment: Some("#"),
+            block_comment: None,
+        },
+        "sql" => Language {
+            keywords: &[
+                "add", "alter", "and", "as", "by", "create", "delete", "drop", "from", "group",
+                "index", "insert", "into", "join", "key", "not", "null", "on", "or", "order",
+                "primary", "select", "set", "table", "update", "values", "where",
+            ],
+            case_insensitive: true,
+            line_comment: Some("--"),
+            block_comment: Some(("/*", "*/")),
+        },
+        "toml" | "yaml" | "yml" | "ini" | "cfg" | "conf" | "gitignore" => Language {
+            keywords: &["false", "no", "true", "yes"],
+            case_insensitive: false,
+            line_comment: Some("#"),
+            block_comment: None,
+        },
+        "json" => Language {
+            keywords: &["false", "null", "true"],
+            case_insensitive: false,
+            line_comment: None,
+            block_comment: None,
+        },
+        "css" | "scss" => Language {
+            keywords: &[],
+            case_insensitive: false,
+            line_comment: None,
+            block_comment: Some(("/*", "*/")),
+        },
+        "xml" => Language {
+            keywords: &[],
+            case_insensitive: false,
+            line_comment: None,
+            block_comment: Some(("<!--", "-->")),
+        },
+        "lua" => Language {
+            keywords: &[
+                "and", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+                "local", "nil", "not", "or", "return", "then", "true", "while",
+            ],
+            case_insensitive: false,
+            line_comment: Some("--"),
+            block_comment: None,
+        },
+        _ => PLAIN,
+    }
+}
+
+/// Returns whether files with the given extension (or name, for files such as `Makefile`)
+/// should be rendered as highlighted text.
+pub fn is_text_extension(extension: &str) -> bool {
+    TEXT_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders source code as escaped HTML, wrapping comments, strings, numbers and keywords
+/// in `<span>` elements with the `com`, `str`, `num` and `kw` classes respectively.
+///
+/// This is a simple tokenizer rather than a real parser, so it only aims to be good enough
+/// to make common languages easier to read. Plain text, such as `.txt` or `.log` files, is only
+/// escaped.
+pub fn highlight(source: &str, extension: &str) -> String {
+    let language = language_for(&extension.to_lowercase());
+    if language.is_plain() {
+        return escape_html(source);
+    }
+    let mut html = String::with_capacity(source.len() * 2);
+    let mut rest = source;
+    // Where the last scan for the end of a literal started with each quote stopped, if it failed
+    let mut unclosed: Vec<(char, usize)> = Vec::new();
+
+    while let Some(c) = rest.chars().next() {
+        let token_len = if let Some(len) = comment_len(rest, &language) {
+            push_span(&mut html, "com", &rest[..len]);
+            len
+        } else if c == '"' || c == '\'' || c == '`' {
+            let offset = source.len() - rest.len();
+            let known_unclosed = unclosed
+                .iter()
+                .any(|(quote, end)| *quote == c && offset < *end);
+            let scan = if known_unclosed {
+                Err(0)
+            } else {
+                string_len(rest, c)
+            };
+            match scan {
+                Ok(len) => {
+                    push_span(&mut html, "str", &rest[..len]);
+                    len
+                }
+                Err(scanned) => {
+                    // The quotes up to where the scan stopped were all escaped, so none of
+                    // them can start a literal either, which keeps long lines linear
+                    if !known_unclosed {
+                        unclosed.retain(|(quote, _)| *quote != c);
+                        unclosed.push((c, offset + scanned));
+                    }
+                    html.push_str(&escape_html(&rest[..c.len_utf8()]));
+                    c.len_utf8()
+                }
+            }
+        } else if c.is_ascii_digit() {
+            let len = number_len(rest);
+            push_span(&mut html, "num", &rest[..len]);
+            len
+        } else if c.is_alphabetic() || c == '_' {
+            let len = word_len(rest);
+            let word = &rest[..len];
+            let is_keyword = if language.case_insensitive {
+                language.keywords.contains(&word.to_lowercase().as_str())
+            } else {
+                language.keywords.contains(&word)
+            };
+            if is_keyword {
+                push_span(&mut html, "kw", word);
+            } else {
+                html.push_str(word);
+            }
+            len
+        } else {
+            html.push_str(&escape_html(&rest[..c.len_utf8()]));
+            c.len_utf8()
+        };
+
+        rest = &rest[token_len..];
+    }
+
+    html
+}
+
+fn push_span(html: &mut String, class: &str, text: &str) {
+    html.push_str(&format!("<span class=\"{}\">{}</span>", class, escape_html(text)));
+}
+
+fn comment_len(text: &str, language: &Language) -> Option<usize> {
+    if let Some((start, end)) = language.block_comment {
+        if let Some(body) = text.strip_prefix(start) {
+            return Some(match body.find(end) {
+                Some(index) => start.len() + index + end.len(),
+                None => text.len(),
+            });
+        }
+    }
+    if let Some(start) = language.line_comment {
+        if text.starts_with(start) {
+            return Some(text.find('\n').unwrap_or(text.len()));
+        }
+    }
+
+    None
+}
+
+/// Returns the length of the string literal at the start of the text, which must end on the
+/// same line (except for backtick strings) for the literal to be recognized. Otherwise, returns
+/// the length of the text which was scanned without finding the end of the literal.
+fn string_len(text: &str, quote: char) -> Result<usize, usize> {
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return Ok(index + c.len_utf8());
+        } else if c == '\n' && quote != '`' {
+            return Err(index);
+        }
+    }
+
+    Err(text.len())
+}
+
+fn word_len(text: &str) -> usize {
+    text.char_indices()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map(|(index, _)| index)
+        .unwrap_or(text.len())
+}
+
+fn number_len(text: &str) -> usize {
+    text.char_indices()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_' || *c == '.'))
+        .map(|(index, _)| index)
+        .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_tokens() {
+        assert_eq!(
+            highlight("let x = \"a<b\"; // 1", "rs"),
+            "<span class=\"kw\">let</span> x = <span class=\"str\">&quot;a&lt;b&quot;</span>; \
+             <span class=\"com\">// 1</span>"
+        );
+        assert_eq!(
+            highlight("SELECT 42", "sql"),
+            "<span class=\"kw\">SELECT</span> <span class=\"num\">42</span>"
+        );
+    }
+
+    #[test]
+    fn leaves_unclosed_quotes_alone() {
+        assert_eq!(highlight("it's", "py"), "it&#39;s");
+        assert_eq!(
+            highlight("'a \\' b\n'c'", "py"),
+            "&#39;a \\&#39; b\n<span class=\"str\">&#39;c&#39;</span>"
+        );
+    }
+
+    #[test]
+    fn scans_long_lines_of_quotes_once() {
+        let source = format!("'{}", "\\'".repeat(100_000));
+        let html = highlight(&source, "js");
+        assert!(!html.contains("<span"));
+        assert_eq!(html.matches("&#39;").count(), 100_001);
+    }
+
+    #[test]
+    fn only_escapes_plain_text() {
+        assert_eq!(highlight("it's 2 <b>", "txt"), "it&#39;s 2 &lt;b&gt;");
+        assert!(is_text_extension("TXT"));
+        assert!(is_text_extension("makefile"));
+        assert!(!is_text_extension("png"));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::models::user::ThrottleKind;
+use crate::utils;
+use diesel::SqliteConnection;
+use rocket::http::Status;
+use std::net::IpAddr;
+
+/// Number of seconds after which failed login attempts are forgotten.
+pub const FAILURE_WINDOW: i64 = 24 * 60 * 60;
+/// Longest number of seconds during which logins can be refused.
+const MAX_LOCKOUT: i64 = 24 * 60 * 60;
+
+/// Refuses a login attempt while its account or client address is locked.
+///
+/// This has to be checked before the password is verified, so that refused attempts neither
+/// reveal anything nor cost the server a password hash. Accounts are identified by the email
+/// address the login was attempted with, so unregistered addresses are locked just the same.
+pub fn check(
+    email: &str,
+    ip: Option<IpAddr>,
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    check_keys(keys(email, ip), now, conn)
+}
+
+/// Counts a failed login attempt against its account and client address, locking them once
+/// there were too many attempts.
+pub fn record_failure(
+    email: &str,
+    ip: Option<IpAddr>,
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    record_failures(keys(email, ip), now, conn)
+}
+
+/// Refuses an attempt to unlock a password-protected share while its link or client address
+/// is locked, like `check` does for logins.
+pub fn check_share(
+    link: &str,
+    ip: Option<IpAddr>,
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    check_keys(share_keys(link, ip), now, conn)
+}
+
+/// Counts a wrong share password against the link and client address, like `record_failure`
+/// does for logins.
+pub fn record_share_failure(
+    link: &str,
+    ip: Option<IpAddr>,
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    record_failures(share_keys(link, ip), now, conn)
+}
+
+fn check_keys(
+    keys: Vec<(ThrottleKind, String)>,
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    for (kind, value) in keys {
+        let locked_until = db::throttle::get(kind.as_str(), &value, conn)?
+            .filter(|throttle| throttle.is_locked(now))
+            .and_then(|throttle| throttle.locked_until);
+        if let Some(locked_until) = locked_until {
+            Err(CustomError::new(
+                format!(
+                    "Too many failed attempts, please try again in {} seconds",
+                    locked_until - now
+                ),
+                Status::TooManyRequests,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn record_failures(
+    keys: Vec<(ThrottleKind, String)>,
+    now: i64,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    for (kind, value) in keys {
+        let failures =
+            db::throttle::record_failure(kind.as_str(), &value, now, now - FAILURE_WINDOW, conn)?;
+        let max_failures = utils::max_login_failures(kind == ThrottleKind::Ip);
+        if let Some(lockout) = lockout_seconds(failures, max_failures) {
+            warn!(
+                "Refusing attempts for {} {} during {} seconds after {} failures",
+                kind.as_str(),
+                value,
+                lockout,
+                failures
+            );
+            db::throttle::lock(kind.as_str(), &value, now + lockout, conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the number of seconds during which logins are refused after the given number of
+/// failed attempts, if there were too many of them. The lockout doubles with every other
+/// failure.
+fn lockout_seconds(failures: i32, max_failures: i64) -> Option<i64> {
+    if i64::from(failures) < max_failures {
+        return None;
+    }
+
+    let doublings = (i64::from(failures) - max_failures).min(32) as u32;
+    let lockout = utils::login_lockout_seconds()
+        .saturating_mul(2_i64.saturating_pow(doublings))
+        .min(MAX_LOCKOUT);
+
+    Some(lockout)
+}
+
+/// Forgets the failed login attempts of an account once its user logged in. Those of the
+/// client address are kept, as logging into one account must not allow guessing others.
+pub fn record_success(email: &str, conn: &SqliteConnection) -> Result<(), ApiError> {
+    db::throttle::delete_throttle(ThrottleKind::Account.as_str(), &account_key(email), conn)?;
+
+    Ok(())
+}
+
+/// Returns how an account is identified in the throttles.
+pub fn account_key(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn keys(email: &str, ip: Option<IpAddr>) -> Vec<(ThrottleKind, String)> {
+    let mut keys = vec![(ThrottleKind::Account, account_key(email))];
+    if let Some(ip) = ip {
+        keys.push((ThrottleKind::Ip, ip.to_string()));
+    }
+
+    keys
+}
+
+fn share_keys(link: &str, ip: Option<IpAddr>) -> Vec<(ThrottleKind, String)> {
+    let mut keys = vec![(ThrottleKind::Share, link.to_string())];
+    if let Some(ip) = ip {
+        keys.push((ThrottleKind::Ip, ip.to_string()));
+    }
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::Connection;
+
+    fn database() -> SqliteConnection {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        crate::embedded_migrations::run(&conn).unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn doubles_lockouts_up_to_a_day() {
+        assert_eq!(lockout_seconds(4, 5), None);
+        assert_eq!(lockout_seconds(5, 5), Some(60));
+        assert_eq!(lockout_seconds(6, 5), Some(120));
+        assert_eq!(lockout_seconds(8, 5), Some(480));
+        assert_eq!(lockout_seconds(i32::MAX, 5), Some(MAX_LOCKOUT));
+    }
+
+    #[test]
+    fn locks_accounts_after_too_many_failures() {
+        let conn = database();
+        let ip = Some("192.0.2.1".parse().unwrap());
+        for _ in 0..4 {
+            record_failure("user@example.com", ip, 1000, &conn).unwrap();
+        }
+        assert!(check("user@example.com", ip, 1000, &conn).is_ok());
+
+        // Email addresses are compared without case
+        record_failure(" User@Example.com", None, 1000, &conn).unwrap();
+        assert!(check("user@example.com", None, 1000, &conn).is_err());
+        assert!(check("other@example.com", ip, 1000, &conn).is_ok());
+        assert!(check("user@example.com", None, 1060, &conn).is_ok());
+
+        record_success("user@example.com", &conn).unwrap();
+        assert!(check("user@example.com", None, 1000, &conn).is_ok());
+    }
+
+    #[test]
+    fn forgets_old_failures() {
+        let conn = database();
+        for _ in 0..4 {
+            record_failure("user@example.com", None, 1000, &conn).unwrap();
+        }
+        record_failure("user@example.com", None, 1000 + FAILURE_WINDOW, &conn).unwrap();
+
+        let throttle = db::throttle::get("account", "user@example.com", &conn)
+            .unwrap()
+            .unwrap();
+        assert_eq!(throttle.failures, 1);
+        assert!(!throttle.is_locked(1000 + FAILURE_WINDOW));
+    }
+
+    #[test]
+    fn locks_client_addresses_across_accounts() {
+        let conn = database();
+        let ip = Some("192.0.2.1".parse().unwrap());
+        for attempt in 0..20 {
+            let email = format!("user{}@example.com", attempt);
+            record_failure(&email, ip, 1000, &conn).unwrap();
+        }
+
+        assert!(check("someone@example.com", ip, 1000, &conn).is_err());
+        assert!(check("someone@example.com", None, 1000, &conn).is_ok());
+    }
+
+    #[test]
+    fn locks_share_links_separately_from_accounts() {
+        let conn = database();
+        for _ in 0..5 {
+            record_share_failure("holidays", None, 1000, &conn).unwrap();
+        }
+
+        assert!(check_share("holidays", None, 1000, &conn).is_err());
+        assert!(check_share("other-share", None, 1000, &conn).is_ok());
+        assert!(check("holidays", None, 1000, &conn).is_ok());
+    }
+}
@@ -0,0 +1,392 @@
+use crate::utils;
+use crate::MailQueue;
+use parking_lot::Mutex;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_SENDER: &str = "filesha@localhost";
+const DEFAULT_SMTP_PORT: u16 = 25;
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// A way of delivering emails.
+pub trait Mailer: Send {
+    fn send(&self, email: &Email) -> io::Result<()>;
+}
+
+/// Creates the mailer configured with `MAIL_TRANSPORT`, which is one of:
+///
+/// - `smtp`: sends emails to the server at `SMTP_HOST` and `SMTP_PORT`, logging in with
+///   `SMTP_USERNAME` and `SMTP_PASSWORD` when they are set. Connections are not encrypted,
+///   so this should be a relay running on the same host or network, and logging in is refused
+///   unless `SMTP_ALLOW_INSECURE_AUTH` is set, since it would send the credentials in clear.
+/// - `file`: appends emails to the file at `MAIL_FILE`.
+/// - `stdout`: prints emails, which is enough when only the administrator needs them.
+/// - `disabled` (default): drops emails, since printing them would let anyone who can read the
+///   logs use the tokens they contain. `REQUIRE_EMAIL_VERIFICATION` cannot be set then.
+///
+/// Emails are sent from `MAIL_FROM`.
+pub fn from_env() -> Result<Box<dyn Mailer>, String> {
+    let sender = env::var("MAIL_FROM").unwrap_or_else(|_| DEFAULT_SENDER.to_string());
+    let transport = env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "disabled".to_string());
+
+    match transport.as_str() {
+        "smtp" => {
+            let host = env::var("SMTP_HOST").map_err(|_| "SMTP_HOST must be set".to_string())?;
+            let port = match env::var("SMTP_PORT") {
+                Ok(port) => port
+                    .parse::<u16>()
+                    .map_err(|_| "SMTP_PORT must be a port number".to_string())?,
+                Err(_) => DEFAULT_SMTP_PORT,
+            };
+            let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+                (Ok(username), Ok(password)) => Some((username, password)),
+                _ => None,
+            };
+            if credentials.is_some() && env::var("SMTP_ALLOW_INSECURE_AUTH").is_err() {
+                return Err(
+                    "SMTP_ALLOW_INSECURE_AUTH must be set to log in over unencrypted connections"
+                        .to_string(),
+                );
+            }
+
+            Ok(Box::new(SmtpMailer {
+                host,
+                port,
+                sender,
+                credentials,
+            }))
+        }
+        "file" => {
+            let path = env::var("MAIL_FILE").map_err(|_| "MAIL_FILE must be set".to_string())?;
+
+            Ok(Box::new(FileMailer {
+                sender,
+                path: Some(PathBuf::from(path)),
+            }))
+        }
+        "stdout" => Ok(Box::new(FileMailer { sender, path: None })),
+        // Users could never verify their address, and thus never upload files
+        "disabled" if utils::email_verification_required() => Err(
+            "REQUIRE_EMAIL_VERIFICATION cannot be set when MAIL_TRANSPORT is disabled".to_string(),
+        ),
+        "disabled" => Ok(Box::new(DisabledMailer)),
+        _ => Err(format!("Unknown MAIL_TRANSPORT {}", transport)),
+    }
+}
+
+/// Starts the background worker which delivers emails.
+///
+/// Sending emails from the worker keeps slow mail servers from delaying responses, and makes
+/// sure that the time a response takes does not reveal whether an email was sent.
+pub fn start_worker(mailer: Box<dyn Mailer>) -> MailQueue {
+    let (sender, receiver) = mpsc::channel::<Email>();
+    thread::spawn(move || {
+        for email in receiver {
+            if let Err(e) = mailer.send(&email) {
+                warn!("Could not send an email: {}", e);
+            }
+        }
+    });
+
+    Arc::new(Mutex::new(sender))
+}
+
+/// Queues an email for delivery.
+pub fn enqueue(queue: &MailQueue, email: Email) {
+    let _ = queue.lock().send(email);
+}
+
+pub struct SmtpMailer {
+    host: String,
+    port: u16,
+    sender: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        let message = format_message(&self.sender, email)?;
+
+        let stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(SMTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(SMTP_TIMEOUT))?;
+        let mut connection = SmtpConnection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        connection.expect_reply(220)?;
+        let domain = self.sender.rsplit('@').next().unwrap_or("localhost");
+        connection.command(&format!("EHLO {}", domain), 250)?;
+        if let Some((username, password)) = &self.credentials {
+            let token = base64::encode(format!("\0{}\0{}", username, password));
+            connection.command(&format!("AUTH PLAIN {}", token), 235)?;
+        }
+        connection.command(&format!("MAIL FROM:<{}>", self.sender), 250)?;
+        connection.command(&format!("RCPT TO:<{}>", email.to), 250)?;
+        connection.command("DATA", 354)?;
+        // Lines starting with a dot must be escaped, since a single dot ends the message
+        let escaped = message.replace("\r\n.", "\r\n..");
+        connection.command(&format!("{}\r\n.", escaped), 250)?;
+        connection.command("QUIT", 221)?;
+
+        Ok(())
+    }
+}
+
+struct SmtpConnection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl SmtpConnection {
+    fn command(&mut self, command: &str, expected_code: u16) -> io::Result<()> {
+        self.writer.write_all(command.as_bytes())?;
+        self.writer.write_all(b"\r\n")?;
+        self.writer.flush()?;
+
+        self.expect_reply(expected_code)
+    }
+
+    /// Reads a reply, which can span several lines, and checks its code.
+    fn expect_reply(&mut self, expected_code: u16) -> io::Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "The SMTP server closed the connection",
+                ));
+            }
+
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            if code != Some(expected_code) {
+                return Err(io::Error::new(
+                    ErrorKind::Other,
+                    format!("Unexpected SMTP reply: {}", line.trim_end()),
+                ));
+            }
+            // Every line but the last one has a hyphen after the code
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Writes emails to a file, or to the standard output when no path is given.
+pub struct FileMailer {
+    sender: String,
+    path: Option<PathBuf>,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        let message = format!("{}\r\n\r\n", format_message(&self.sender, email)?);
+
+        match &self.path {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(message.as_bytes()),
+            None => io::stdout().write_all(message.as_bytes()),
+        }
+    }
+}
+
+pub struct DisabledMailer;
+
+impl Mailer for DisabledMailer {
+    fn send(&self, _email: &Email) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Formats an email as an RFC 5322 message, with lines ending with CRLF.
+fn format_message(sender: &str, email: &Email) -> io::Result<String> {
+    let headers = [sender, &email.to, &email.subject];
+    if headers
+        .iter()
+        .any(|header| header.contains('\r') || header.contains('\n'))
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Email headers must not contain line breaks",
+        ));
+    }
+
+    let body = email.body.replace("\r\n", "\n").replace('\n', "\r\n");
+    Ok(format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}",
+        sender,
+        email.to,
+        email.subject,
+        time::now_utc().rfc822(),
+        body
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::net::TcpListener;
+    use tempfile::tempdir;
+
+    fn email(body: &str) -> Email {
+        Email {
+            to: "user@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    fn reply(command: &str) -> &'static str {
+        match command.split(' ').next().unwrap_or("") {
+            "EHLO" => "250-stand-in.example.com\r\n250-SIZE 1000000\r\n250 AUTH PLAIN",
+            "AUTH" => "235 Authenticated",
+            "MAIL" | "RCPT" => "250 OK",
+            "DATA" => "354 End data with <CR><LF>.<CR><LF>",
+            "QUIT" => "221 Bye",
+            _ => "500 Unknown command",
+        }
+    }
+
+    /// Starts a stand-in SMTP server which answers the commands of a single client with
+    /// `reply`, and returns its port along with the lines it will have received.
+    fn stand_in_server(reply: fn(&str) -> &'static str) -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            writer
+                .write_all(b"220 stand-in.example.com ESMTP\r\n")
+                .unwrap();
+
+            let mut received = Vec::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return received;
+                }
+                let line = line.trim_end_matches("\r\n").to_string();
+                received.push(line.clone());
+                let response = match (in_data, line.as_str()) {
+                    (true, ".") => "250 Queued",
+                    (true, _) => continue,
+                    (false, command) => reply(command),
+                };
+                in_data = response.starts_with("354");
+                writer.write_all(response.as_bytes()).unwrap();
+                writer.write_all(b"\r\n").unwrap();
+            }
+        });
+
+        (port, server)
+    }
+
+    fn smtp_mailer(port: u16, credentials: Option<(String, String)>) -> SmtpMailer {
+        SmtpMailer {
+            host: "127.0.0.1".to_string(),
+            port,
+            sender: "filesha@example.com".to_string(),
+            credentials,
+        }
+    }
+
+    #[test]
+    fn sends_emails_over_smtp() {
+        let (port, server) = stand_in_server(reply);
+        let credentials = Some(("filesha".to_string(), "secret".to_string()));
+        smtp_mailer(port, credentials)
+            .send(&email("First line\n.hidden\n."))
+            .unwrap();
+
+        let received = server.join().unwrap();
+        assert_eq!(received[0], "EHLO example.com");
+        assert_eq!(
+            received[1],
+            format!("AUTH PLAIN {}", base64::encode("\0filesha\0secret"))
+        );
+        assert_eq!(received[2], "MAIL FROM:<filesha@example.com>");
+        assert_eq!(received[3], "RCPT TO:<user@example.com>");
+        assert_eq!(received[4], "DATA");
+        assert!(received.contains(&"Subject: Hello".to_string()));
+        // Lines starting with a dot are escaped, so that the message does not end early
+        let body = received
+            .iter()
+            .position(|line| line == "First line")
+            .unwrap();
+        assert_eq!(received[body + 1], "..hidden");
+        assert_eq!(received[body + 2], "..");
+        assert_eq!(received[body + 3], ".");
+        assert_eq!(received[body + 4], "QUIT");
+        assert_eq!(received.len(), body + 5);
+    }
+
+    #[test]
+    fn fails_on_rejected_commands() {
+        let (port, server) = stand_in_server(|command| {
+            if command.starts_with("RCPT") {
+                "550 No such user here"
+            } else {
+                reply(command)
+            }
+        });
+        let error = smtp_mailer(port, None).send(&email("Hi")).unwrap_err();
+        assert!(error.to_string().contains("550 No such user here"));
+
+        let received = server.join().unwrap();
+        assert_eq!(received.last().unwrap(), "RCPT TO:<user@example.com>");
+    }
+
+    #[test]
+    fn refuses_line_breaks_in_headers() {
+        let mut injected = email("Hi");
+        injected.subject = "Hello\r\nBcc: someone@example.com".to_string();
+        let error = format_message("filesha@example.com", &injected).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+        let mut injected = email("Hi");
+        injected.to = "user@example.com\nBcc: someone@example.com".to_string();
+        assert!(format_message("filesha@example.com", &injected).is_err());
+
+        let message = format_message("filesha@example.com", &email("Hi\nthere")).unwrap();
+        assert!(message.ends_with("\r\n\r\nHi\r\nthere"));
+    }
+
+    #[test]
+    fn appends_emails_to_the_mail_file() {
+        let directory = tempdir().unwrap();
+        let path = directory.path().join("mail.txt");
+        fs::write(&path, "Earlier emails\r\n").unwrap();
+        let mailer = FileMailer {
+            sender: "filesha@example.com".to_string(),
+            path: Some(path.clone()),
+        };
+        mailer.send(&email("First")).unwrap();
+        mailer.send(&email("Second")).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("Earlier emails\r\n"));
+        assert_eq!(contents.matches("To: user@example.com\r\n").count(), 2);
+        assert!(contents.find("First").unwrap() < contents.find("Second").unwrap());
+    }
+}
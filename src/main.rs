@@ -10,41 +10,84 @@ extern crate lazy_static;
 extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
+#[macro_use]
+extern crate log;
 
 use diesel::prelude::*;
 use dotenv::dotenv;
-use parking_lot::RwLock;
+use parking_lot::Mutex;
+use rocket::fairing::AdHoc;
 use rocket_contrib::databases::diesel::SqliteConnection;
-use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use uuid::Uuid;
 
+mod access;
 mod api_error;
+mod email_verification;
 mod guards;
+mod highlight;
+mod login_throttle;
+mod mailer;
+mod pages;
+mod password_policy;
+mod password_resets;
 mod passwords;
+mod preview;
+mod qr;
 mod schema;
+mod share_log;
+mod thumbnails;
+mod tokens;
+mod totp;
 mod utils;
 mod db {
     pub mod file;
+    pub mod grant;
+    pub mod group;
+    pub mod invitation;
+    pub mod session;
+    pub mod throttle;
+    pub mod token;
+    pub mod totp;
     pub mod user;
+
+    no_arg_sql_function!(
+        last_insert_rowid,
+        diesel::sql_types::BigInt,
+        "The ID of the last row inserted through the connection."
+    );
 }
 mod models {
     pub mod common_models;
     pub mod file;
+    pub mod grant;
+    pub mod group;
+    pub mod invitation;
+    pub mod token;
+    pub mod totp;
     pub mod user;
 }
 mod routes {
+    pub mod admin;
     pub mod file;
+    pub mod grant;
+    pub mod group;
+    pub mod invitation;
+    pub mod totp;
     pub mod user;
 }
 
 embed_migrations!();
 
-type SessionStore = Arc<RwLock<HashMap<Uuid, models::user::ActiveSession>>>;
-type PendingUploadStore = Arc<RwLock<HashMap<Uuid, models::file::PendingUpload>>>;
+type ThumbnailQueue = Arc<Mutex<Sender<PathBuf>>>;
+type MailQueue = Arc<Mutex<Sender<mailer::Email>>>;
+type PasswordResetQueue = Arc<Mutex<Sender<String>>>;
+/// IDs of accesses to shares, along with the number of bytes sent for them
+type ShareLogQueue = Arc<Mutex<Sender<(i32, u64)>>>;
 
 #[database("data_db")]
 pub struct DBConnection(SqliteConnection);
@@ -57,28 +100,80 @@ fn main() {
         .expect("Could not connect to database");
     embedded_migrations::run_with_output(&connection, &mut std::io::stdout())
         .expect("Could not apply database migrations");
-    let active_session_ids: SessionStore = Arc::new(RwLock::new(HashMap::new()));
-    let pending_uploads: PendingUploadStore = Arc::new(RwLock::new(HashMap::new()));
-
-    let pending_uploads_thread = pending_uploads.clone();
-    thread::spawn(move || loop {
-        thread::sleep(Duration::from_secs(60 * 60)); // Run the cleanup every hour
-        let new_upload_store = utils::remove_old_pending_uploads(&*pending_uploads_thread.read());
-        *pending_uploads_thread.write() = new_upload_store;
-    });
+    passwords::init();
+    let storage_root = env::var("STORAGE_LOCATION").expect("STORAGE_LOCATION must be set");
+    let unconverted_shares = db::file::convert_legacy_shares(Path::new(&storage_root), &connection)
+        .expect("Could not convert the paths of existing shares");
+    access::recount_usage(&connection).expect("Could not count the space used by files");
+    let thumbnail_queue = thumbnails::start_worker();
+    let mail_queue = mailer::start_worker(mailer::from_env().expect("Invalid mail configuration"));
+    let reset_queue = password_resets::start_worker(database_url.clone(), mail_queue.clone());
+    let share_log_queue = share_log::start_worker(database_url.clone());
 
-    let active_session_ids_thread = active_session_ids.clone();
-    thread::spawn(move || loop {
-        thread::sleep(Duration::from_secs(60 * 60)); // Run the cleanup every hour
-        let new_active_sessions = utils::remove_old_sessions(&*active_session_ids_thread.read());
-        *active_session_ids_thread.write() = new_active_sessions;
+    thread::spawn(move || {
+        let connection = SqliteConnection::establish(&database_url)
+            .expect("Could not connect to database");
+        loop {
+            thread::sleep(Duration::from_secs(60 * 60)); // Run the cleanup every hour
+            let now = utils::unix_timestamp();
+            let _ = db::file::delete_expired_shares(now, &connection);
+            let _ = db::file::delete_unused_files(&connection);
+            let retention = utils::share_log_retention_days().saturating_mul(24 * 60 * 60);
+            let _ = db::file::delete_old_share_accesses(now.saturating_sub(retention), &connection);
+            // Keep pending uploads for one day
+            let _ = db::file::delete_old_pending_uploads(now - 24 * 60 * 60, &connection);
+            let _ = db::session::delete_expired_sessions(now, &connection);
+            let _ = db::user::delete_expired_password_resets(now, &connection);
+            let _ = db::user::delete_expired_email_verifications(now, &connection);
+            let _ = db::throttle::delete_old_throttles(
+                now - login_throttle::FAILURE_WINDOW,
+                now,
+                &connection,
+            );
+        }
     });
 
     rocket::ignite()
         .attach(DBConnection::fairing())
+        .attach(AdHoc::on_launch("Unconverted shares", move |_| {
+            for link in unconverted_shares {
+                warn!(
+                    "Share {} is not stored in STORAGE_LOCATION, it will stay unavailable until it is",
+                    link
+                );
+            }
+        }))
         .mount(
             "/user",
-            routes![routes::user::register, routes::user::login],
+            routes![
+                routes::user::register,
+                routes::user::login,
+                routes::user::me,
+                routes::user::update_profile,
+                routes::user::change_password,
+                routes::user::change_email,
+                routes::user::request_email_verification,
+                routes::user::verify_email,
+                routes::user::request_password_reset,
+                routes::user::reset_password,
+                routes::user::logout,
+                routes::user::list_sessions,
+                routes::user::revoke_session,
+                routes::user::revoke_all_sessions,
+                routes::user::create_token,
+                routes::user::list_tokens,
+                routes::user::revoke_token,
+            ],
+        )
+        .mount(
+            "/user/totp",
+            routes![
+                routes::totp::totp_status,
+                routes::totp::setup_totp,
+                routes::totp::totp_qr_code,
+                routes::totp::confirm_totp,
+                routes::totp::disable_totp,
+            ],
         )
         .mount(
             "/file",
@@ -87,18 +182,88 @@ fn main() {
                 routes::file::upload,
                 routes::file::ls,
                 routes::file::mkdir,
+                routes::file::move_file,
                 routes::file::download,
+                routes::file::preview,
+                routes::file::thumbnail,
                 routes::file::create_share,
-                routes::file::download_shared
+                routes::file::list_shares,
+                routes::file::inspect_share,
+                routes::file::share_stats,
+                routes::file::share_log,
+                routes::file::share_qr_code,
+                routes::file::revoke_share,
+                routes::file::download_shared,
+                routes::file::download_shared_root,
+                routes::file::download_shared_path,
+                routes::file::browse_shared,
+                routes::file::shared_preview,
+                routes::file::shared_path_preview,
+                routes::file::shared_thumbnail,
+                routes::file::shared_path_thumbnail,
+                routes::file::new_drop_upload,
+                routes::file::drop_upload,
+                routes::file::unlock_form,
+                routes::file::unlock_shared,
+                routes::file::unlock_shared_form
+            ],
+        )
+        .mount(
+            "/grant",
+            routes![
+                routes::grant::create_grant,
+                routes::grant::list_grants,
+                routes::grant::revoke_grant,
+                routes::grant::shared_with_me
+            ],
+        )
+        .mount(
+            "/group",
+            routes![
+                routes::group::create_group,
+                routes::group::list_groups,
+                routes::group::inspect_group,
+                routes::group::update_group,
+                routes::group::delete_group,
+                routes::group::add_member,
+                routes::group::remove_member
+            ],
+        )
+        .mount(
+            "/admin",
+            routes![
+                routes::admin::list_users,
+                routes::admin::get_user,
+                routes::admin::set_role,
+                routes::admin::disable_user,
+                routes::admin::enable_user,
+                routes::admin::set_password,
+                routes::admin::set_quota,
+                routes::admin::logout_user,
+                routes::admin::list_lockouts,
+                routes::admin::unlock,
+                routes::admin::list_unowned_shares,
+                routes::admin::revoke_unowned_share
+            ],
+        )
+        .mount(
+            "/invitation",
+            routes![
+                routes::invitation::create_invitation,
+                routes::invitation::list_invitations,
+                routes::invitation::revoke_invitation
             ],
         )
         .register(catchers![
             api_error::unauthorized,
+            api_error::forbidden,
             api_error::not_found,
             api_error::unprocessable_entity,
             api_error::server_error,
         ])
-        .manage(active_session_ids)
-        .manage(pending_uploads)
+        .manage(thumbnail_queue)
+        .manage(mail_queue)
+        .manage(reset_queue)
+        .manage(share_log_queue)
         .launch();
 }
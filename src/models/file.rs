@@ -1,15 +1,26 @@
 use crate::api_error::{ApiError, CustomError};
-use crate::models::user::User;
-use crate::schema::shares;
+use crate::schema::{files, pending_uploads, share_accesses, shares};
+use crate::utils::{self, Namespace};
+use diesel::sql_types::{BigInt, Nullable};
 use rocket::http::Status;
+use rocket::response::content::Html;
+use rocket::response::{self, NamedFile, Redirect, Responder};
+use rocket::Request;
+use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::path::{Component, PathBuf};
-use std::time::Instant;
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct JsonPath {
     pub path: String,
+    /// ID of a grant received from another user, in which case the path is relative to the
+    /// file or directory they shared
+    pub grant: Option<i32>,
+    /// ID of a group the user is a member of, in which case the path is relative to the
+    /// group's directory
+    pub group: Option<i32>,
 }
 
 impl JsonPath {
@@ -32,19 +43,142 @@ impl JsonPath {
     }
 }
 
+#[derive(Deserialize)]
+pub struct ShareCreate {
+    #[serde(flatten)]
+    pub path: JsonPath,
+    /// Number of seconds after which the share link stops working
+    pub expires_in: Option<u64>,
+    /// Password required to access the shared content
+    pub password: Option<String>,
+    /// Number of times the shared content can be downloaded
+    pub max_downloads: Option<u32>,
+    /// Whether the share gives access to the content or only accepts uploads
+    #[serde(default)]
+    pub kind: ShareKind,
+    /// Maximum size of each file uploaded to a file drop
+    pub max_file_size: Option<u64>,
+    /// Maximum size of all the files uploaded to a file drop
+    pub max_total_size: Option<u64>,
+    /// Readable link to use instead of a random one
+    pub slug: Option<String>,
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareKind {
+    /// The shared content can be browsed and downloaded
+    Download,
+    /// Anyone with the link can upload files to the shared directory, but not see its content
+    Drop,
+}
+
+impl ShareKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShareKind::Download => "download",
+            ShareKind::Drop => "drop",
+        }
+    }
+}
+
+impl Default for ShareKind {
+    fn default() -> Self {
+        ShareKind::Download
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DropFile {
+    pub name: String,
+}
+
+#[derive(Deserialize, FromForm)]
+pub struct ShareUnlock {
+    pub password: String,
+}
+
 #[derive(Serialize)]
 pub struct UploadID {
     pub upload_id: uuid::Uuid,
 }
 
-#[derive(Clone)]
+/// An upload waiting for its data, which can be sent until it is a day old.
+#[table_name = "pending_uploads"]
+#[derive(Insertable, Queryable)]
 pub struct PendingUpload {
-    pub created: Instant,
-    pub path: PathBuf,
-    pub user: User,
+    pub id: String,
+    pub path: String,
+    /// The user allowed to send the data, when the upload was not created through a file drop
+    pub user_id: Option<i32>,
+    /// The file drop the data can be sent to, for anonymous uploads
+    pub share_link: Option<String>,
+    /// The user whose quota the upload counts towards, if any
+    pub quota_user_id: Option<i32>,
+    /// The group whose quota the upload counts towards, if any
+    pub quota_group_id: Option<i32>,
+    pub created_at: i64,
 }
 
-#[derive(Debug, Serialize)]
+impl PendingUpload {
+    pub fn new(
+        id: Uuid,
+        path: &Path,
+        uploader: Uploader,
+        namespace: Option<Namespace>,
+    ) -> Result<PendingUpload, ApiError> {
+        let (user_id, share_link) = match uploader {
+            Uploader::User(user_id) => (Some(user_id), None),
+            Uploader::Share(link) => (None, Some(link)),
+        };
+        let (quota_user_id, quota_group_id) = match namespace {
+            Some(Namespace::User(user_id)) => (Some(user_id), None),
+            Some(Namespace::Group(group_id)) => (None, Some(group_id)),
+            None => (None, None),
+        };
+
+        Ok(PendingUpload {
+            id: id.to_string(),
+            path: path
+                .to_str()
+                .ok_or(ApiError::InternalServerError)?
+                .to_string(),
+            user_id,
+            share_link,
+            quota_user_id,
+            quota_group_id,
+            created_at: utils::unix_timestamp(),
+        })
+    }
+
+    pub fn uploader(&self) -> Option<Uploader> {
+        match (self.user_id, &self.share_link) {
+            (Some(user_id), _) => Some(Uploader::User(user_id)),
+            (None, Some(link)) => Some(Uploader::Share(link.to_string())),
+            (None, None) => None,
+        }
+    }
+
+    /// The namespace whose quota the upload counts towards, if any
+    pub fn namespace(&self) -> Option<Namespace> {
+        match (self.quota_user_id, self.quota_group_id) {
+            (Some(user_id), _) => Some(Namespace::User(user_id)),
+            (None, Some(group_id)) => Some(Namespace::Group(group_id)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Who is allowed to send the data of a pending upload.
+#[derive(Clone, PartialEq)]
+pub enum Uploader {
+    /// The ID of the user who created the upload
+    User(i32),
+    /// Anyone with the link to the file drop share
+    Share(String),
+}
+
+#[derive(Debug, PartialEq, Serialize)]
 pub enum FileSystemElementType {
     File,
     Directory,
@@ -68,9 +202,233 @@ impl fmt::Debug for DirContents {
     }
 }
 
+/// The different ways the content of a share can be returned, depending on what it is and on
+/// what the client asked for.
+pub enum SharedContent {
+    File(NamedFile),
+    Page(Html<String>),
+    Listing(Json<DirContents>),
+    Redirect(Box<Redirect>),
+}
+
+impl<'r> Responder<'r> for SharedContent {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            SharedContent::File(file) => file.respond_to(request),
+            SharedContent::Page(page) => page.respond_to(request),
+            SharedContent::Listing(listing) => listing.respond_to(request),
+            SharedContent::Redirect(redirect) => redirect.respond_to(request),
+        }
+    }
+}
+
+#[table_name = "files"]
+#[derive(Insertable)]
+pub struct NewTrackedFile {
+    pub owner_id: i32,
+    pub path: String,
+}
+
+/// A file or directory which shares refer to, whose path is updated whenever it is moved.
+#[derive(Queryable)]
+pub struct TrackedFile {
+    pub id: i32,
+    pub owner_id: i32,
+    /// Path relative to the owner's directory
+    pub path: String,
+}
+
+impl TrackedFile {
+    pub fn full_path(&self) -> PathBuf {
+        let root = utils::root_path(Namespace::User(self.owner_id));
+        if self.path.is_empty() {
+            root
+        } else {
+            root.join(&self.path)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FileMove {
+    #[serde(flatten)]
+    pub path: JsonPath,
+    /// Where to move the file, relative to the same directory as `path`
+    pub destination: String,
+}
+
 #[table_name = "shares"]
-#[derive(Insertable, Queryable, Serialize)]
+#[derive(Insertable, Queryable)]
 pub struct Share {
     pub link: String,
-    pub path: String
+    /// The shared file or directory, which is missing only for shares whose legacy path could
+    /// not be converted
+    pub file_id: Option<i32>,
+    pub expires_at: Option<i64>,
+    pub password: Option<String>,
+    pub remaining_downloads: Option<i32>,
+    pub user_id: Option<i32>,
+    pub created_at: i64,
+    pub kind: String,
+    pub max_file_size: Option<i64>,
+    pub max_total_size: Option<i64>,
+    pub uploaded_bytes: i64,
+    /// Absolute path of the shared content, for shares created before files were tracked
+    pub legacy_path: Option<String>,
+}
+
+impl Share {
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= utils::unix_timestamp(),
+            None => false,
+        }
+    }
+
+    pub fn is_drop(&self) -> bool {
+        self.kind == ShareKind::Drop.as_str()
+    }
+}
+
+/// A share as seen by its owner, which never reveals where the shared content is stored.
+#[derive(Serialize)]
+pub struct ShareResult {
+    pub link: String,
+    /// Path of the shared content in the owner's directory, unless it can no longer be found
+    pub path: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub password_protected: bool,
+    pub remaining_downloads: Option<i32>,
+    pub kind: String,
+    pub max_file_size: Option<i64>,
+    pub max_total_size: Option<i64>,
+    pub uploaded_bytes: i64,
+}
+
+impl ShareResult {
+    pub fn from(share: &Share, file: Option<&TrackedFile>) -> ShareResult {
+        ShareResult {
+            link: share.link.to_string(),
+            path: file.map(|file| file.path.to_string()),
+            created_at: share.created_at,
+            expires_at: share.expires_at,
+            password_protected: share.password.is_some(),
+            remaining_downloads: share.remaining_downloads,
+            kind: share.kind.to_string(),
+            max_file_size: share.max_file_size,
+            max_total_size: share.max_total_size,
+            uploaded_bytes: share.uploaded_bytes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ShareList {
+    pub shares: Vec<ShareResult>,
+}
+
+#[table_name = "share_accesses"]
+#[derive(Insertable)]
+pub struct NewShareAccess {
+    pub link: String,
+    pub accessed_at: i64,
+    /// Client address, without its last bits when IP anonymisation is enabled
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub bytes_served: i64,
+    /// HTTP status of the response
+    pub status: i32,
+}
+
+#[derive(Queryable)]
+pub struct ShareAccess {
+    pub accessed_at: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub bytes_served: i64,
+    pub status: i32,
+}
+
+impl ShareAccess {
+    /// Returns whether the share was served, which `db::file::get_share_stats` decides the same
+    /// way.
+    pub fn is_success(&self) -> bool {
+        self.status < 400
+    }
+}
+
+#[derive(Serialize)]
+pub struct ShareAccessResult {
+    pub accessed_at: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub bytes_served: i64,
+    pub status: i32,
+    pub success: bool,
+}
+
+impl ShareAccessResult {
+    pub fn from(access: &ShareAccess) -> ShareAccessResult {
+        ShareAccessResult {
+            accessed_at: access.accessed_at,
+            ip: access.ip.clone(),
+            user_agent: access.user_agent.clone(),
+            bytes_served: access.bytes_served,
+            status: access.status,
+            success: access.is_success(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ShareAccessLog {
+    pub accesses: Vec<ShareAccessResult>,
+}
+
+/// Statistics over the accesses to a share which are still within the retention period.
+#[derive(Serialize)]
+pub struct ShareStats {
+    pub accesses: u64,
+    pub successful_accesses: u64,
+    pub failed_accesses: u64,
+    pub bytes_served: i64,
+    /// Number of distinct client addresses, which are only approximate when anonymised
+    pub unique_visitors: u64,
+    pub first_access: Option<i64>,
+    pub last_access: Option<i64>,
+}
+
+/// The statistics of a share as computed by the database, see `db::file::get_share_stats`.
+#[derive(QueryableByName)]
+pub struct ShareAccessSummary {
+    #[sql_type = "BigInt"]
+    pub accesses: i64,
+    #[sql_type = "BigInt"]
+    pub failed_accesses: i64,
+    #[sql_type = "BigInt"]
+    pub bytes_served: i64,
+    #[sql_type = "BigInt"]
+    pub unique_visitors: i64,
+    #[sql_type = "Nullable<BigInt>"]
+    pub first_access: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub last_access: Option<i64>,
+}
+
+impl ShareStats {
+    pub fn from(summary: &ShareAccessSummary) -> ShareStats {
+        let accesses = summary.accesses.max(0) as u64;
+        let failed_accesses = summary.failed_accesses.max(0) as u64;
+
+        ShareStats {
+            accesses,
+            successful_accesses: accesses.saturating_sub(failed_accesses),
+            failed_accesses,
+            bytes_served: summary.bytes_served,
+            unique_visitors: summary.unique_visitors.max(0) as u64,
+            first_access: summary.first_access,
+            last_access: summary.last_access,
+        }
+    }
 }
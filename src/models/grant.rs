@@ -0,0 +1,106 @@
+use crate::models::file::{FileSystemElementType, JsonPath};
+use crate::models::user::UserResult;
+use crate::schema::grants;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct GrantCreate {
+    #[serde(flatten)]
+    pub path: JsonPath,
+    /// Email address of the user receiving access
+    pub email: String,
+    pub permission: Permission,
+}
+
+/// What a user may do with the files shared with them.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// The files can be listed and downloaded
+    Read,
+    /// The files can also be uploaded and directories created
+    Write,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+        }
+    }
+
+    pub fn parse(permission: &str) -> Option<Permission> {
+        match permission {
+            "read" => Some(Permission::Read),
+            "write" => Some(Permission::Write),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this permission is enough to do what the required permission allows.
+    pub fn allows(&self, required: Permission) -> bool {
+        *self == Permission::Write || required == Permission::Read
+    }
+}
+
+#[table_name = "grants"]
+#[derive(Insertable)]
+pub struct NewGrant {
+    pub owner_id: i32,
+    pub grantee_id: i32,
+    pub path: String,
+    pub permission: String,
+    pub created_at: i64,
+}
+
+#[derive(Queryable)]
+pub struct Grant {
+    pub id: i32,
+    pub owner_id: i32,
+    pub grantee_id: i32,
+    /// Path of the shared file or directory, relative to the owner's storage directory
+    pub path: String,
+    pub permission: String,
+    pub created_at: i64,
+}
+
+impl Grant {
+    pub fn permission(&self) -> Permission {
+        // Unknown values can only come from a manual edit, so fall back to the safest permission
+        Permission::parse(&self.permission).unwrap_or(Permission::Read)
+    }
+}
+
+/// A grant as seen by the user who gave it.
+#[derive(Serialize)]
+pub struct GrantResult {
+    pub id: i32,
+    pub path: String,
+    pub grantee: UserResult,
+    pub permission: String,
+    pub created_at: i64,
+}
+
+/// A grant as seen by the user who received it, which does not reveal where the shared file
+/// or directory is in the owner's storage.
+#[derive(Serialize)]
+pub struct ReceivedGrant {
+    /// The ID to send as `grant` alongside paths, which are then relative to the shared item
+    pub id: i32,
+    pub name: String,
+    pub element_type: FileSystemElementType,
+    pub owner: UserResult,
+    pub permission: String,
+    pub created_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct GrantList {
+    pub grants: Vec<GrantResult>,
+}
+
+#[derive(Serialize)]
+pub struct ReceivedGrantList {
+    pub grants: Vec<ReceivedGrant>,
+}
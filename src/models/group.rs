@@ -0,0 +1,120 @@
+use crate::models::grant::Permission;
+use crate::models::user::UserResult;
+use crate::schema::{group_members, groups};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct GroupCreate {
+    pub name: String,
+    /// Maximum number of bytes the files of the group can take
+    pub quota: Option<u64>,
+}
+
+/// What a member can do in a group.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupRole {
+    /// Can change the group and its members, as well as its files
+    Owner,
+    /// Can read and change the files of the group
+    Member,
+    /// Can only read the files of the group
+    Viewer,
+}
+
+impl GroupRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupRole::Owner => "owner",
+            GroupRole::Member => "member",
+            GroupRole::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<GroupRole> {
+        match role {
+            "owner" => Some(GroupRole::Owner),
+            "member" => Some(GroupRole::Member),
+            "viewer" => Some(GroupRole::Viewer),
+            _ => None,
+        }
+    }
+
+    /// Returns the permission the role gives on the files of the group.
+    pub fn permission(&self) -> Permission {
+        match self {
+            GroupRole::Owner | GroupRole::Member => Permission::Write,
+            GroupRole::Viewer => Permission::Read,
+        }
+    }
+}
+
+#[table_name = "groups"]
+#[derive(Insertable)]
+pub struct NewGroup {
+    pub name: String,
+    pub quota: Option<i64>,
+    pub created_at: i64,
+}
+
+#[derive(Queryable)]
+pub struct Group {
+    pub id: i32,
+    pub name: String,
+    pub quota: Option<i64>,
+    pub created_at: i64,
+    /// Number of bytes the files of the group take
+    pub used_bytes: i64,
+}
+
+#[table_name = "group_members"]
+#[derive(Insertable, Queryable)]
+pub struct GroupMember {
+    pub group_id: i32,
+    pub user_id: i32,
+    pub role: String,
+}
+
+impl GroupMember {
+    pub fn role(&self) -> GroupRole {
+        // Unknown values can only come from a manual edit, so fall back to the weakest role
+        GroupRole::parse(&self.role).unwrap_or(GroupRole::Viewer)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MemberAdd {
+    /// Email address of the user to add, or whose role should change
+    pub email: String,
+    pub role: GroupRole,
+}
+
+/// A group as seen by one of its members.
+#[derive(Serialize)]
+pub struct GroupResult {
+    pub id: i32,
+    pub name: String,
+    /// The role of the current user in the group
+    pub role: String,
+    pub quota: Option<i64>,
+    pub used_bytes: u64,
+    pub created_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct GroupList {
+    pub groups: Vec<GroupResult>,
+}
+
+#[derive(Serialize)]
+pub struct MemberResult {
+    pub user: UserResult,
+    pub role: String,
+}
+
+#[derive(Serialize)]
+pub struct GroupDetails {
+    #[serde(flatten)]
+    pub group: GroupResult,
+    pub members: Vec<MemberResult>,
+}
@@ -0,0 +1,86 @@
+use crate::schema::invitations;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct InvitationCreate {
+    /// Email address the invitation is reserved to, anyone can use it otherwise
+    pub email: Option<String>,
+    /// Number of accounts which can be created with the invitation, one by default
+    pub max_uses: Option<u32>,
+    /// Number of seconds after which the invitation stops working
+    pub expires_in: Option<u64>,
+}
+
+#[table_name = "invitations"]
+#[derive(Insertable)]
+pub struct NewInvitation {
+    /// SHA-256 digest of the invitation code, which is only known by the administrator
+    pub code_hash: String,
+    pub created_by: i32,
+    pub email: Option<String>,
+    pub max_uses: i32,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Queryable)]
+pub struct Invitation {
+    pub id: i32,
+    pub created_by: i32,
+    pub email: Option<String>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl Invitation {
+    /// Returns whether an account with the given email address can be created with this
+    /// invitation.
+    pub fn allows(&self, email: &str, now: i64) -> bool {
+        self.uses < self.max_uses
+            && self.expires_at.map_or(true, |expires_at| now < expires_at)
+            && self
+                .email
+                .as_ref()
+                .map_or(true, |invited| invited.eq_ignore_ascii_case(email))
+    }
+}
+
+#[derive(Serialize)]
+pub struct InvitationResult {
+    pub id: i32,
+    pub created_by: i32,
+    pub email: Option<String>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl InvitationResult {
+    pub fn from(invitation: &Invitation) -> InvitationResult {
+        InvitationResult {
+            id: invitation.id,
+            created_by: invitation.created_by,
+            email: invitation.email.clone(),
+            max_uses: invitation.max_uses,
+            uses: invitation.uses,
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
+
+/// A newly created invitation, the only time its code is sent to the administrator.
+#[derive(Serialize)]
+pub struct CreatedInvitation {
+    pub code: String,
+    #[serde(flatten)]
+    pub details: InvitationResult,
+}
+
+#[derive(Serialize)]
+pub struct InvitationList {
+    pub invitations: Vec<InvitationResult>,
+}
@@ -0,0 +1,113 @@
+use crate::schema::api_tokens;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct TokenCreate {
+    /// Name reminding the user where the token is used
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Number of seconds after which the token stops working
+    pub expires_in: Option<u64>,
+}
+
+/// What an API token can be used for.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Listing, downloading and previewing files
+    Read,
+    /// Uploading files and creating directories
+    Upload,
+    /// Creating, inspecting and revoking shares
+    Shares,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Upload => "upload",
+            Scope::Shares => "shares",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "read" => Some(Scope::Read),
+            "upload" => Some(Scope::Upload),
+            "shares" => Some(Scope::Shares),
+            _ => None,
+        }
+    }
+}
+
+#[table_name = "api_tokens"]
+#[derive(Insertable)]
+pub struct NewApiToken {
+    pub user_id: i32,
+    pub name: String,
+    /// SHA-256 digest of the token, which is only known by the user
+    pub token_hash: String,
+    /// Comma-separated list of scopes
+    pub scopes: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Queryable)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+}
+
+impl ApiToken {
+    pub fn scopes(&self) -> Vec<Scope> {
+        // Unknown values can only come from a manual edit, so they are ignored
+        self.scopes.split(',').filter_map(Scope::parse).collect()
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| now >= expires_at)
+    }
+}
+
+#[derive(Serialize)]
+pub struct TokenResult {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+}
+
+impl TokenResult {
+    pub fn from(token: &ApiToken) -> TokenResult {
+        TokenResult {
+            id: token.id,
+            name: token.name.to_string(),
+            scopes: token.scopes(),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at,
+        }
+    }
+}
+
+/// A newly created token, the only time its value is sent to the user.
+#[derive(Serialize)]
+pub struct CreatedToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub details: TokenResult,
+}
+
+#[derive(Serialize)]
+pub struct TokenList {
+    pub tokens: Vec<TokenResult>,
+}
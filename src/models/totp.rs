@@ -0,0 +1,55 @@
+use crate::schema::{recovery_codes, totp_secrets};
+use serde::{Deserialize, Serialize};
+
+#[table_name = "totp_secrets"]
+#[derive(Insertable, Queryable)]
+pub struct TotpSecret {
+    pub user_id: i32,
+    /// Base32-encoded secret shared with the authenticator application
+    pub secret: String,
+    /// Whether the user confirmed the enrollment, until then the secret is not required to log in
+    pub enabled: bool,
+    /// Time step of the last accepted code, which cannot be used a second time
+    pub last_used_step: Option<i64>,
+    pub created_at: i64,
+}
+
+#[table_name = "recovery_codes"]
+#[derive(Insertable)]
+pub struct NewRecoveryCode {
+    pub user_id: i32,
+    pub code_hash: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCode {
+    /// A code from the authenticator application, or a recovery code
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpDisable {
+    pub password: String,
+    /// A code from the authenticator application, or a recovery code
+    pub code: String,
+}
+
+/// What a user needs to add their account to an authenticator application.
+#[derive(Serialize)]
+pub struct TotpSetup {
+    pub secret: String,
+    /// `otpauth://` URI, which can also be scanned as a QR code
+    pub uri: String,
+}
+
+#[derive(Serialize)]
+pub struct TotpStatus {
+    pub enabled: bool,
+    pub remaining_recovery_codes: i64,
+}
+
+/// Codes which can each be used once instead of a code from the authenticator application.
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
@@ -1,6 +1,6 @@
-use crate::schema::users;
+use crate::schema::{email_verifications, login_throttles, password_resets, sessions, users};
+use crate::utils;
 use serde::{Deserialize, Serialize};
-use std::time::Instant;
 
 #[derive(PartialEq, Deserialize, Queryable, Clone)]
 pub struct User {
@@ -8,6 +8,59 @@ pub struct User {
     pub email: String,
     pub display_name: String,
     pub password: String,
+    pub email_verified: bool,
+    pub role: String,
+    /// Disabled users can neither login nor use their sessions and API tokens
+    pub disabled: bool,
+    /// Maximum number of bytes the files of the user can take
+    pub quota: Option<i64>,
+    /// Number of bytes the files of the user take
+    pub used_bytes: i64,
+}
+
+impl User {
+    /// Returns the role of the user. Users listed in `ADMIN_EMAILS` are always administrators,
+    /// so that the first ones can be appointed.
+    pub fn role(&self) -> Role {
+        if self.is_configured_admin() {
+            Role::Admin
+        } else {
+            Role::parse(&self.role).unwrap_or(Role::User)
+        }
+    }
+
+    /// Returns whether the user is an administrator through `ADMIN_EMAILS`, which requires them
+    /// to have verified their email address so that nobody can register with one of these
+    /// addresses to become an administrator.
+    pub fn is_configured_admin(&self) -> bool {
+        self.email_verified && utils::is_admin(&self.email)
+    }
+}
+
+/// What a user can do on the server.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    /// Can manage users, invitations and login lockouts
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
 }
 
 #[table_name = "users"]
@@ -18,11 +71,20 @@ pub struct UserCreate {
     pub password: String,
 }
 
+#[derive(Deserialize)]
+pub struct Registration {
+    #[serde(flatten)]
+    pub user: UserCreate,
+    /// Invitation code, which is required unless registrations are open to everyone
+    pub invitation: Option<String>,
+}
+
 #[derive(Serialize, Queryable)]
 pub struct UserResult {
     pub id: i32,
     pub email: String,
     pub display_name: String,
+    pub email_verified: bool,
 }
 
 impl UserResult {
@@ -31,18 +93,232 @@ impl UserResult {
             id: user.id,
             email: user.email.to_string(),
             display_name: user.display_name.to_string(),
+            email_verified: user.email_verified,
         }
     }
 }
 
+/// A user as seen by administrators.
+#[derive(Serialize)]
+pub struct UserDetails {
+    #[serde(flatten)]
+    pub user: UserResult,
+    pub role: Role,
+    pub disabled: bool,
+    pub quota: Option<i64>,
+    /// Number of bytes taken by the files of the user
+    pub used_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct UserDetailsList {
+    pub users: Vec<UserDetails>,
+}
+
+#[derive(Deserialize)]
+pub struct RoleUpdate {
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct QuotaUpdate {
+    /// Maximum number of bytes, or nothing to remove the limit
+    pub quota: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordSet {
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct EmailChange {
+    pub email: String,
+    /// The current password of the user, as changing the email address changes how they log in
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ProfileUpdate {
+    pub display_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordReset {
+    /// The token sent by email
+    pub token: String,
+    pub new_password: String,
+}
+
+#[table_name = "password_resets"]
+#[derive(Insertable, Queryable)]
+pub struct PasswordResetToken {
+    /// SHA-256 digest of the token, which is only known by the recipient of the email
+    pub token_hash: String,
+    pub user_id: i32,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Deserialize)]
+pub struct EmailVerificationConfirm {
+    /// The token sent by email
+    pub token: String,
+}
+
+#[table_name = "email_verifications"]
+#[derive(Insertable, Queryable)]
+pub struct EmailVerification {
+    /// SHA-256 digest of the token, which is only known by the recipient of the email
+    pub token_hash: String,
+    pub user_id: i32,
+    /// The address the token was sent to, which must still be the one of the user
+    pub email: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
 #[derive(serde::Deserialize)]
 pub struct UserLogin {
     pub email: String,
     pub password: String,
+    /// Whether the session should last longer and survive periods of inactivity
+    #[serde(default)]
+    pub remember: bool,
+    /// Code from the authenticator application, or a recovery code, for users who enabled
+    /// two-factor authentication
+    pub code: Option<String>,
 }
 
-#[derive(Clone)]
+#[table_name = "sessions"]
+#[derive(Clone, Insertable, Queryable)]
 pub struct ActiveSession {
-    pub email: String,
-    pub created: Instant,
+    /// SHA-256 hash of the key sent in the private cookie, it is never exposed through the API
+    pub id: String,
+    /// Identifies the session in the API
+    pub public_id: String,
+    pub user_id: i32,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub expires_at: i64,
+    /// Number of seconds without any request after which the session expires
+    pub idle_timeout: Option<i64>,
+}
+
+impl ActiveSession {
+    pub fn is_expired(&self, now: i64) -> bool {
+        now >= self.expires_at
+            || self
+                .idle_timeout
+                .map_or(false, |idle_timeout| now >= self.last_seen + idle_timeout)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionResult {
+    pub id: String,
+    pub created_at: i64,
+    pub last_seen: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub expires_at: i64,
+    /// Whether this is the session used to send the request
+    pub current: bool,
+}
+
+impl SessionResult {
+    pub fn from(session: &ActiveSession, current: bool) -> SessionResult {
+        SessionResult {
+            id: session.public_id.to_string(),
+            created_at: session.created_at,
+            last_seen: session.last_seen,
+            user_agent: session.user_agent.clone(),
+            ip: session.ip.clone(),
+            expires_at: session.expires_at,
+            current,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionList {
+    pub sessions: Vec<SessionResult>,
+}
+
+/// What failed login attempts, and attempts to unlock shares, are counted against.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThrottleKind {
+    /// The email address the login was attempted with, whether it is registered or not
+    Account,
+    /// The address of the client which attempted to login or to unlock a share
+    Ip,
+    /// The link of the password-protected share which was attempted to be unlocked
+    Share,
+}
+
+impl ThrottleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleKind::Account => "account",
+            ThrottleKind::Ip => "ip",
+            ThrottleKind::Share => "share",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<ThrottleKind> {
+        match kind {
+            "account" => Some(ThrottleKind::Account),
+            "ip" => Some(ThrottleKind::Ip),
+            "share" => Some(ThrottleKind::Share),
+            _ => None,
+        }
+    }
+}
+
+#[table_name = "login_throttles"]
+#[derive(Clone, Insertable, Queryable)]
+pub struct LoginThrottle {
+    pub kind: String,
+    /// Lowercase email address, client address or share link, depending on the kind
+    pub value: String,
+    /// Number of failed login attempts since the last successful one
+    pub failures: i32,
+    pub last_failure: i64,
+    /// Logins are refused until then
+    pub locked_until: Option<i64>,
+}
+
+impl LoginThrottle {
+    pub fn is_locked(&self, now: i64) -> bool {
+        self.locked_until
+            .map_or(false, |locked_until| now < locked_until)
+    }
+}
+
+#[derive(Serialize)]
+pub struct LockoutResult {
+    pub kind: ThrottleKind,
+    pub value: String,
+    pub failures: i32,
+    pub last_failure: i64,
+    pub locked_until: i64,
+}
+
+#[derive(Serialize)]
+pub struct LockoutList {
+    pub lockouts: Vec<LockoutResult>,
 }
@@ -0,0 +1,163 @@
+use crate::highlight::escape_html;
+use crate::models::file::{DirContents, FileSystemElementType};
+use crate::thumbnails;
+use rocket::http::uri::Uri;
+use std::path::{Path, PathBuf};
+
+const PAGE_STYLE: &str = "body{font-family:sans-serif;max-width:40em;margin:3em auto;\
+                          padding:0 1em;color:#24292e}\
+                          input{font-size:1em;padding:.3em;margin:.2em 0}\
+                          table{border-collapse:collapse;width:100%}td{padding:.3em .5em}\
+                          td img{max-width:64px;max-height:64px}";
+
+const FILE_DROP_SCRIPT: &str = "\
+document.getElementById('drop').onsubmit = async function (event) {\
+  event.preventDefault();\
+  const status = document.getElementById('status');\
+  for (const file of document.getElementById('files').files) {\
+    status.textContent = 'Uploading ' + file.name + '...';\
+    const created = await fetch('SHARE_URL/upload/new', {\
+      method: 'POST',\
+      headers: {'Content-Type': 'application/json'},\
+      body: JSON.stringify({name: file.name})\
+    });\
+    const upload = await created.json();\
+    if (!created.ok) { status.textContent = upload.message; return; }\
+    const sent = await fetch('SHARE_URL/upload/' + upload.upload_id, {method: 'POST', body: file});\
+    if (!sent.ok) { status.textContent = (await sent.json()).message; return; }\
+  }\
+  status.textContent = 'All files were uploaded successfully';\
+};";
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title><style>{}</style></head><body>{}</body></html>",
+        escape_html(title),
+        PAGE_STYLE,
+        body
+    )
+}
+
+/// Renders the form used by browsers to unlock a password-protected share.
+pub fn unlock_form(link: &str) -> String {
+    page(
+        "Protected share",
+        &format!(
+            "<h1>Protected share</h1><p>This share is protected by a password.</p>\
+             <form method=\"post\" action=\"/file/shared/{}/unlock\">\
+             <input type=\"password\" name=\"password\" placeholder=\"Password\" autofocus required> \
+             <input type=\"submit\" value=\"Unlock\"></form>",
+            escape_html(link)
+        ),
+    )
+}
+
+/// Renders a page listing the contents of a directory inside a share.
+///
+/// `path` is the location of the listed directory relative to the shared directory, and is
+/// used to build the links to its subdirectories and files.
+pub fn shared_directory(link: &str, path: &Path, contents: &DirContents) -> String {
+    let share_url = format!("/file/shared/{}", Uri::percent_encode(link));
+    let mut crumbs = format!("<a href=\"{}\">Shared files</a>", share_url);
+    let mut crumb_path = PathBuf::new();
+    for component in path.iter() {
+        crumb_path.push(component);
+        crumbs.push_str(&format!(
+            " / <a href=\"{}/browse/{}\">{}</a>",
+            share_url,
+            encode_path(&crumb_path),
+            escape_html(&component.to_string_lossy())
+        ));
+    }
+
+    let mut rows = String::new();
+    for element in contents.contents.iter() {
+        let element_path = path.join(&element.name);
+        let encoded_path = encode_path(&element_path);
+        let name = escape_html(&element.name);
+        let row = match element.element_type {
+            FileSystemElementType::Directory => format!(
+                "<tr><td></td><td><a href=\"{0}/browse/{1}\">{2}/</a></td><td></td>\
+                 <td><a href=\"{0}/download/{1}\">zip</a></td></tr>",
+                share_url, encoded_path, name
+            ),
+            FileSystemElementType::File => {
+                let thumbnail = if thumbnails::is_supported_image(&element_path) {
+                    format!(
+                        "<img src=\"{}/thumbnail/{}\" alt=\"\" loading=\"lazy\">",
+                        share_url, encoded_path
+                    )
+                } else {
+                    String::new()
+                };
+                format!(
+                    "<tr><td>{3}</td><td><a href=\"{0}/download/{1}\">{2}</a></td><td>{4}</td>\
+                     <td><a href=\"{0}/preview/{1}\">preview</a></td></tr>",
+                    share_url,
+                    encoded_path,
+                    name,
+                    thumbnail,
+                    format_size(element.bytes)
+                )
+            }
+        };
+        rows.push_str(&row);
+    }
+
+    let download_url = if path.as_os_str().is_empty() {
+        format!("{}/download", share_url)
+    } else {
+        format!("{}/download/{}", share_url, encode_path(path))
+    };
+    page(
+        "Shared files",
+        &format!(
+            "<p>{}</p><p><a href=\"{}\">Download everything as a zip file</a></p>\
+             <table>{}</table>",
+            crumbs, download_url, rows
+        ),
+    )
+}
+
+fn encode_path(path: &Path) -> String {
+    path.iter()
+        .map(|component| Uri::percent_encode(&component.to_string_lossy()).to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// Renders the page used by browsers to upload files to a file drop.
+///
+/// The files are sent one by one through the same routes API clients use, which is why this
+/// page needs a small script rather than a plain form.
+pub fn file_drop(link: &str) -> String {
+    let share_url = format!("/file/shared/{}", Uri::percent_encode(link));
+    page(
+        "File drop",
+        &format!(
+            "<h1>File drop</h1><p>Files uploaded here can only be seen by the person who \
+             shared this link.</p><form id=\"drop\"><input type=\"file\" id=\"files\" multiple \
+             required> <input type=\"submit\" value=\"Upload\"></form><p id=\"status\"></p>\
+             <script>{}</script>",
+            FILE_DROP_SCRIPT.replace("SHARE_URL", &share_url)
+        ),
+    )
+}
@@ -0,0 +1,368 @@
+use crate::api_error::{ApiError, ValidationError};
+use crate::utils;
+use ring::digest;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::path::Path;
+
+/// Passwords used the most in data breaches, the most common first, one per line.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+/// Only the beginning of longer passwords is estimated, which is already enough to tell that
+/// they are strong.
+const MAX_ESTIMATED_LENGTH: usize = 100;
+/// Number of characters a part of a password must at least contain to match a word or a
+/// pattern.
+const MIN_MATCH_LENGTH: usize = 3;
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+lazy_static! {
+    /// The rank of each common password, starting from 1.
+    static ref COMMON_PASSWORD_RANKS: HashMap<&'static str, usize> = COMMON_PASSWORDS
+        .lines()
+        .map(str::trim)
+        .filter(|password| !password.is_empty())
+        .enumerate()
+        .map(|(rank, password)| (password, rank + 1))
+        .collect();
+}
+
+/// Checks that a new password is acceptable, returning every reason why it is not as errors
+/// of the given request field.
+///
+/// `user_inputs` are what attackers would try first for this user, such as their email
+/// address and display name.
+pub fn validate(field: &str, password: &str, user_inputs: &[&str]) -> Result<(), ApiError> {
+    let mut errors = Vec::new();
+    let mut invalid = |code: &str, message: String| {
+        errors.push(ValidationError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        })
+    };
+
+    let min_length = utils::password_min_length();
+    if password.chars().count() < min_length {
+        invalid(
+            "too_short",
+            format!("Passwords must contain at least {} characters", min_length),
+        );
+    }
+    if is_breached(password)? {
+        invalid(
+            "breached",
+            "This password appeared in data breaches, attackers will try it first".to_string(),
+        );
+    } else if let Some(min_strength) = utils::password_min_strength() {
+        let estimate = estimate_strength(password, user_inputs);
+        if estimate.score < min_strength {
+            invalid(
+                "too_weak",
+                format!("This password is too easy to guess. {}", estimate.hint),
+            );
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::validation(errors))
+    }
+}
+
+/// Returns whether a password is one of the bundled common passwords, or is listed in
+/// `BREACHED_PASSWORDS_DIR` when it is set.
+///
+/// That directory uses the format of the Have I Been Pwned range API: the uppercase SHA-1
+/// hashes of breached passwords are split in files named after their first 5 hexadecimal
+/// characters, each line holding the rest of a hash, optionally followed by `:` and a count.
+/// Only the file for the hash of the password is read.
+fn is_breached(password: &str) -> Result<bool, ApiError> {
+    if COMMON_PASSWORD_RANKS.contains_key(password.to_lowercase().as_str()) {
+        return Ok(true);
+    }
+    let directory = match env::var("BREACHED_PASSWORDS_DIR") {
+        Ok(directory) => directory,
+        Err(_) => return Ok(false),
+    };
+
+    let hash: String = digest::digest(&digest::SHA1, password.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    let (prefix, suffix) = hash.split_at(5);
+    let file = match File::open(Path::new(&directory).join(prefix)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let listed = line.split(':').next().unwrap_or_default().trim();
+        if listed.eq_ignore_ascii_case(suffix) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// How hard a password is to guess.
+pub struct StrengthEstimate {
+    /// From 0, for passwords guessed almost immediately, to 4, for passwords which are very
+    /// unlikely to ever be guessed
+    pub score: u8,
+    /// How to choose a stronger password
+    pub hint: &'static str,
+}
+
+/// What an attacker would guess a part of a password as.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Pattern {
+    /// Characters guessed one by one
+    BruteForce,
+    Sequence,
+    Repeat,
+    CommonPassword,
+    UserInput,
+}
+
+impl Pattern {
+    fn hint(self) -> &'static str {
+        match self {
+            Pattern::BruteForce => "Add a few more words or characters.",
+            Pattern::Sequence => "Avoid sequences such as abc, 6543 or qwerty.",
+            Pattern::Repeat => "Avoid repeated characters.",
+            Pattern::CommonPassword => {
+                "Avoid common passwords and words, even with capital letters or symbols \
+                 replacing letters."
+            }
+            Pattern::UserInput => "Avoid your name and email address.",
+        }
+    }
+}
+
+/// Estimates the strength of a password, in the way of zxcvbn.
+///
+/// The password is split into the parts an attacker would guess separately: common passwords
+/// and user inputs (also with capital letters or l33t substitutions), repeated characters,
+/// sequences such as `abcd` or `6543`, keyboard rows such as `qwerty`, and any other character
+/// on its own. The estimate is the number of guesses needed for the cheapest split.
+pub fn estimate_strength(password: &str, user_inputs: &[&str]) -> StrengthEstimate {
+    let chars: Vec<char> = password.chars().take(MAX_ESTIMATED_LENGTH).collect();
+    let user_words: Vec<String> = user_inputs
+        .iter()
+        .flat_map(|input| input.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().count() >= MIN_MATCH_LENGTH)
+        .map(str::to_lowercase)
+        .collect();
+
+    // For the first i characters: the base 10 logarithm of the guesses needed, where the
+    // last part of the cheapest split starts, and how it is guessed
+    let mut best = vec![(0.0, 0, Pattern::BruteForce)];
+    for end in 1..=chars.len() {
+        let mut cheapest = (
+            best[end - 1].0 + cardinality(chars[end - 1]).log10(),
+            end - 1,
+            Pattern::BruteForce,
+        );
+        for start in 0..(end + 1).saturating_sub(MIN_MATCH_LENGTH) {
+            if let Some((guesses, pattern)) = match_part(&chars[start..end], &user_words) {
+                let log_guesses = best[start].0 + guesses.log10();
+                if log_guesses < cheapest.0 {
+                    cheapest = (log_guesses, start, pattern);
+                }
+            }
+        }
+        best.push(cheapest);
+    }
+
+    let log_guesses = best[chars.len()].0;
+    let score = match log_guesses {
+        x if x < 3.0 => 0,
+        x if x < 6.0 => 1,
+        x if x < 8.0 => 2,
+        x if x < 10.0 => 3,
+        _ => 4,
+    };
+    // Give the hint about the most obvious part of the password
+    let mut weakest = Pattern::BruteForce;
+    let mut end = chars.len();
+    while end > 0 {
+        let (_, start, pattern) = best[end];
+        if pattern > weakest {
+            weakest = pattern;
+        }
+        end = start;
+    }
+
+    StrengthEstimate {
+        score,
+        hint: weakest.hint(),
+    }
+}
+
+/// Returns the fewest guesses needed for a part of a password as a whole, and how it is
+/// guessed, unless it does not match any pattern.
+fn match_part(part: &[char], user_words: &[String]) -> Option<(f64, Pattern)> {
+    let mut matches = Vec::new();
+    let lowercase: String = part.iter().flat_map(|c| c.to_lowercase()).collect();
+    let unleeted: String = lowercase.chars().map(unleet).collect();
+    let capitalizations = capitalizations(part);
+
+    for (word, variations) in [(&lowercase, 1.0), (&unleeted, 2.0)].iter() {
+        if let Some(rank) = COMMON_PASSWORD_RANKS.get(word.as_str()) {
+            matches.push((
+                *rank as f64 * capitalizations * variations,
+                Pattern::CommonPassword,
+            ));
+        }
+        if user_words.contains(word) {
+            matches.push((capitalizations * variations, Pattern::UserInput));
+        }
+    }
+    if part.iter().all(|c| *c == part[0]) {
+        matches.push((cardinality(part[0]) * part.len() as f64, Pattern::Repeat));
+    }
+    if let Some(step) = sequence_step(part) {
+        let direction = if step > 0 { 1.0 } else { 2.0 };
+        matches.push((
+            cardinality(part[0]) * part.len() as f64 * direction,
+            Pattern::Sequence,
+        ));
+    }
+    let reversed: String = lowercase.chars().rev().collect();
+    for row in KEYBOARD_ROWS.iter() {
+        if row.contains(lowercase.as_str()) {
+            matches.push((10.0 * part.len() as f64, Pattern::Sequence));
+        } else if row.contains(reversed.as_str()) {
+            matches.push((20.0 * part.len() as f64, Pattern::Sequence));
+        }
+    }
+
+    matches
+        .into_iter()
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+}
+
+/// Returns the number of characters an attacker has to try for a character guessed on its
+/// own.
+fn cardinality(c: char) -> f64 {
+    if c.is_ascii_digit() {
+        10.0
+    } else if c.is_ascii_alphabetic() {
+        26.0
+    } else if c.is_ascii() {
+        33.0
+    } else {
+        100.0
+    }
+}
+
+/// Returns how many ways of capitalizing a word have to be tried to guess this part.
+fn capitalizations(part: &[char]) -> f64 {
+    let uppercase = part.iter().filter(|c| c.is_uppercase()).count();
+    let lowercase = part.iter().filter(|c| c.is_lowercase()).count();
+    if uppercase == 0 {
+        1.0
+    } else if lowercase == 0 || (uppercase == 1 && part[0].is_uppercase()) {
+        2.0
+    } else {
+        2_f64.powi(uppercase.min(lowercase) as i32 + 1)
+    }
+}
+
+/// Replaces a character commonly used instead of a letter by this letter.
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '5' | '$' => 's',
+        '7' => 't',
+        _ => c,
+    }
+}
+
+/// Returns 1 or -1 when the characters of this part follow each other, like `abc` or `321`.
+fn sequence_step(part: &[char]) -> Option<i64> {
+    if !part.iter().all(|c| c.is_alphanumeric()) {
+        return None;
+    }
+    let step = part[1] as i64 - part[0] as i64;
+    if step.abs() != 1 {
+        return None;
+    }
+    part.windows(2)
+        .all(|pair| pair[1] as i64 - pair[0] as i64 == step)
+        .then(|| step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(password: &str) -> u8 {
+        estimate_strength(password, &["jane.doe@example.com", "Jane Doe"]).score
+    }
+
+    #[test]
+    fn recognizes_common_passwords() {
+        assert!(is_breached("password").unwrap());
+        assert!(is_breached("DRAGON").unwrap());
+        assert!(!is_breached("correct horse battery staple").unwrap());
+    }
+
+    #[test]
+    fn scores_guessable_passwords_low() {
+        assert_eq!(score(""), 0);
+        assert_eq!(score("password"), 0);
+        assert_eq!(score("P4ssw0rd"), 0);
+        assert_eq!(score("qwertyuiop"), 0);
+        assert_eq!(score("aaaaaaaaaaaa"), 0);
+        assert_eq!(score("abcdefgh"), 0);
+        assert!(score("JaneDoe") <= 1);
+    }
+
+    #[test]
+    fn scores_long_random_passwords_high() {
+        assert_eq!(score("correct horse battery staple"), 4);
+        assert_eq!(score("x7$Kq!2vLp"), 4);
+    }
+
+    #[test]
+    fn hints_at_the_most_obvious_part() {
+        let estimate = estimate_strength("janedoe1", &["Jane Doe", "janedoe@example.com"]);
+        assert_eq!(estimate.hint, Pattern::UserInput.hint());
+        let estimate = estimate_strength("abcdef", &[]);
+        assert_eq!(estimate.hint, Pattern::Sequence.hint());
+    }
+
+    #[test]
+    fn finds_sequences() {
+        assert_eq!(sequence_step(&['a', 'b', 'c']), Some(1));
+        assert_eq!(sequence_step(&['6', '5', '4', '3']), Some(-1));
+        assert_eq!(sequence_step(&['a', 'c', 'e']), None);
+        assert_eq!(sequence_step(&['!', '"', '#']), None);
+    }
+
+    #[test]
+    fn counts_capitalizations() {
+        let chars = |word: &str| word.chars().collect::<Vec<_>>();
+        assert_eq!(capitalizations(&chars("dragon")) as u32, 1);
+        assert_eq!(capitalizations(&chars("Dragon")) as u32, 2);
+        assert_eq!(capitalizations(&chars("DRAGON")) as u32, 2);
+        assert_eq!(capitalizations(&chars("DrAgon")) as u32, 8);
+    }
+
+    #[test]
+    fn refuses_short_and_breached_passwords() {
+        assert!(validate("password", "correct horse battery staple", &[]).is_ok());
+        assert!(validate("password", "x7$K", &[]).is_err());
+        assert!(validate("password", "password1", &[]).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::api_error::ApiError;
+use crate::db;
+use crate::mailer::{self, Email};
+use crate::models::user::PasswordResetToken;
+use crate::tokens;
+use crate::utils;
+use crate::{MailQueue, PasswordResetQueue};
+use diesel::prelude::*;
+use parking_lot::Mutex;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+/// Number of seconds during which a password reset token can be used.
+pub const PASSWORD_RESET_LIFETIME: i64 = 60 * 60;
+/// Number of seconds after a password reset token was sent during which no other one is sent to
+/// the same user, so that their mailbox cannot be flooded.
+const PASSWORD_RESET_INTERVAL: i64 = 5 * 60;
+
+/// Starts the background worker which creates password reset tokens and emails them.
+///
+/// Requests are handled entirely in the background, so that the time `request_password_reset`
+/// takes to respond does not reveal whether an email address is registered, which mirrors the
+/// constant-time approach of `login`.
+pub fn start_worker(database_url: String, mail_queue: MailQueue) -> PasswordResetQueue {
+    let (sender, receiver) = mpsc::channel::<String>();
+    thread::spawn(move || {
+        let connection =
+            SqliteConnection::establish(&database_url).expect("Could not connect to database");
+        for email in receiver {
+            if send_reset_token(&email, &mail_queue, &connection).is_err() {
+                warn!("Could not create a password reset token");
+            }
+        }
+    });
+
+    Arc::new(Mutex::new(sender))
+}
+
+/// Queues a password reset for the user with the given email address, if there is one.
+pub fn enqueue(queue: &PasswordResetQueue, email: String) {
+    let _ = queue.lock().send(email);
+}
+
+fn send_reset_token(
+    email: &str,
+    mail_queue: &MailQueue,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let user = match db::user::get_by_email(email, conn)? {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    let now = utils::unix_timestamp();
+    if db::user::count_password_resets_since(user.id, now - PASSWORD_RESET_INTERVAL, conn)? > 0 {
+        return Ok(());
+    }
+
+    let token = tokens::random_string()?;
+    db::user::create_password_reset(
+        &PasswordResetToken {
+            token_hash: tokens::hash_token(&token),
+            user_id: user.id,
+            created_at: now,
+            expires_at: now + PASSWORD_RESET_LIFETIME,
+        },
+        conn,
+    )?;
+    mailer::enqueue(
+        mail_queue,
+        Email {
+            to: user.email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Hello {},\n\n\
+                 Someone asked to reset the password of your account. If it was you, use \
+                 this token to choose a new password within the next hour:\n\n{}\n\n\
+                 If it was not you, you can ignore this email and keep using your password.\n",
+                user.display_name, token
+            ),
+        },
+    );
+
+    Ok(())
+}
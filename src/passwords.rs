@@ -1,9 +1,18 @@
+extern crate argon2;
 extern crate base64;
 extern crate ring;
+use crate::api_error::{ApiError, CustomError};
+use crate::login_throttle;
+use crate::models::user::User;
+use crate::utils;
+use diesel::SqliteConnection;
 use ring::rand::{SecureRandom, SystemRandom};
 use ring::{digest, pbkdf2};
+use rocket::http::Status;
+use std::env;
 use std::error::Error;
 use std::fmt;
+use std::net::IpAddr;
 
 lazy_static! {
     static ref RANDOMNESS_SOURCE: SystemRandom = {
@@ -13,27 +22,120 @@ lazy_static! {
 
         randomness_source
     };
+    static ref CURRENT_ARGON2_PARAMETERS: Argon2Parameters = Argon2Parameters::from_env()
+        .unwrap_or_else(|e| panic!("Invalid Argon2 parameters: {}", e));
+    /// Hash verified in place of the ones of users who do not exist, see `verify_login_password`
+    static ref DUMMY_ARGON2_HASH: PasswordHash =
+        hash_password("Dummy Password").expect("Could not make a dummy password hash");
 }
 static DIGEST_ALG: &'static digest::Algorithm = &digest::SHA512;
-const CREDENTIAL_LEN: usize = digest::SHA512_OUTPUT_LEN;
-const ITERATIONS: u32 = 100_000;
+const ARGON2_HASH_LEN: u32 = 32;
+const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
 
-pub struct PasswordHash {
-    hash: Vec<u8>,
-    iterations: u32,
-    salt: Vec<u8>,
+pub enum PasswordHash {
+    /// PBKDF2-HMAC-SHA512, which passwords used to be hashed with. These hashes are only verified
+    /// anymore, and replaced when their user logs in.
+    Pbkdf2 {
+        hash: Vec<u8>,
+        iterations: u32,
+        salt: Vec<u8>,
+    },
+    /// Argon2id, kept as the PHC string produced by `argon2`, which contains its parameters
+    Argon2(String),
 }
 
-impl PasswordHash {
-    fn new(hash: Vec<u8>, iterations: u32, salt: Vec<u8>) -> PasswordHash {
-        PasswordHash {
-            hash,
-            iterations,
-            salt,
+/// The cost parameters of Argon2id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Argon2Parameters {
+    /// Memory used, in KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Argon2Parameters {
+    /// Returns the parameters new hashes are made with, which are `ARGON2_MEMORY_KIB`,
+    /// `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM` when they are set.
+    ///
+    /// They are read once, by `init` at startup, and invalid ones make the server panic.
+    pub fn current() -> Argon2Parameters {
+        *CURRENT_ARGON2_PARAMETERS
+    }
+
+    fn from_env() -> Result<Argon2Parameters, String> {
+        let parameter = |name: &str, default: u32| match env::var(name) {
+            Ok(value) => value
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|value| *value >= 1)
+                .ok_or_else(|| format!("{} must be a positive integer", name)),
+            Err(_) => Ok(default),
+        };
+        let parameters = Argon2Parameters {
+            memory: parameter("ARGON2_MEMORY_KIB", DEFAULT_ARGON2_MEMORY_KIB)?,
+            iterations: parameter("ARGON2_ITERATIONS", DEFAULT_ARGON2_ITERATIONS)?,
+            parallelism: parameter("ARGON2_PARALLELISM", DEFAULT_ARGON2_PARALLELISM)?,
+        };
+        // Some combinations are refused, such as less than 8 KiB of memory per lane
+        argon2::hash_encoded(b"password", &[0; 16], &parameters.config())
+            .map_err(|e| format!("Argon2 refuses these parameters: {}", e))?;
+
+        Ok(parameters)
+    }
+
+    fn config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.memory,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            thread_mode: argon2::ThreadMode::from_threads(self.parallelism),
+            hash_length: ARGON2_HASH_LEN,
+            ..argon2::Config::default()
         }
     }
 
+    /// Reads the parameters of a PHC string such as `$argon2id$v=19$m=19456,t=2,p=1$salt$hash`.
+    fn from_phc(encoded: &str) -> Option<Argon2Parameters> {
+        let mut memory = None;
+        let mut iterations = None;
+        let mut parallelism = None;
+        for parameter in encoded.split('$').nth(3)?.split(',') {
+            let mut parts = parameter.splitn(2, '=');
+            let name = parts.next()?;
+            let value = parts.next()?.parse::<u32>().ok()?;
+            match name {
+                "m" => memory = Some(value),
+                "t" => iterations = Some(value),
+                "p" => parallelism = Some(value),
+                _ => return None,
+            }
+        }
+
+        Some(Argon2Parameters {
+            memory: memory?,
+            iterations: iterations?,
+            parallelism: parallelism?,
+        })
+    }
+}
+
+impl PasswordHash {
+    /// Parses a stored hash, which is either a PHC string (`$argon2id$...` or
+    /// `$pbkdf2-sha512$i=iterations$salt$hash`) or a PBKDF2 hash in the legacy
+    /// `base64(salt)$iterations$base64(hash)` format.
     pub fn from(string: &str) -> Result<PasswordHash, PasswordError> {
+        if string.starts_with("$argon2id$") {
+            return Ok(PasswordHash::Argon2(string.to_string()));
+        }
+        if let Some(phc) = string.strip_prefix("$pbkdf2-sha512$") {
+            return PasswordHash::from_pbkdf2_phc(phc);
+        }
+
         let mut hash_parts = string.split("$");
         let encoded_salt = hash_parts
             .next()
@@ -69,28 +171,83 @@ impl PasswordHash {
             }
         };
 
-        Ok(PasswordHash {
+        Ok(PasswordHash::Pbkdf2 {
             hash: password_hash,
             iterations,
             salt,
         })
     }
 
+    fn from_pbkdf2_phc(phc: &str) -> Result<PasswordHash, PasswordError> {
+        let mut hash_parts = phc.split('$');
+        let iterations = hash_parts
+            .next()
+            .and_then(|parameters| parameters.strip_prefix("i="))
+            .and_then(|iterations| iterations.parse::<u32>().ok())
+            .ok_or_else(|| {
+                PasswordError::new("The hash does not contain a valid iteration count")
+            })?;
+        let mut decode_part = |name: &str| {
+            hash_parts
+                .next()
+                .and_then(|part| base64::decode_config(part, base64::STANDARD_NO_PAD).ok())
+                .ok_or_else(|| {
+                    PasswordError::new(&format!("The hash does not contain a valid {}", name))
+                })
+        };
+        let salt = decode_part("salt")?;
+        let hash = decode_part("hashed password")?;
+
+        Ok(PasswordHash::Pbkdf2 {
+            hash,
+            iterations,
+            salt,
+        })
+    }
+
+    /// Formats the hash as a PHC string, which is how hashes are stored.
     pub fn to_string(&self) -> String {
-        [
-            base64::encode(&self.salt),
-            ITERATIONS.to_string(),
-            base64::encode(&self.hash),
-        ]
-        .join("$")
+        match self {
+            PasswordHash::Pbkdf2 {
+                hash,
+                iterations,
+                salt,
+            } => format!(
+                "$pbkdf2-sha512$i={}${}${}",
+                iterations,
+                base64::encode_config(salt, base64::STANDARD_NO_PAD),
+                base64::encode_config(hash, base64::STANDARD_NO_PAD)
+            ),
+            PasswordHash::Argon2(encoded) => encoded.to_string(),
+        }
+    }
+
+    /// Returns whether the hash should be replaced by a new one once the password is known,
+    /// because it was not made with Argon2id and the current parameters.
+    pub fn needs_rehash(&self) -> bool {
+        match self {
+            PasswordHash::Pbkdf2 { .. } => true,
+            PasswordHash::Argon2(encoded) => {
+                !encoded.starts_with("$argon2id$v=19$")
+                    || Argon2Parameters::from_phc(encoded) != Some(Argon2Parameters::current())
+            }
+        }
     }
 }
 
-/// Returns a secure hash of the input password containing the salt, iterations and hashed password.
+/// Reads the Argon2 parameters and makes the dummy hash `verify_login_password` needs, which is
+/// done at startup so that invalid parameters are noticed at once and the first logins do not
+/// take longer than the others.
+pub fn init() {
+    lazy_static::initialize(&DUMMY_ARGON2_HASH);
+}
+
+/// Returns a secure hash of the input password, made with Argon2id and the current
+/// `Argon2Parameters`.
 ///
-/// The returned hash is of the form "base64(salt)$iterations$base64(hash(password.as_bytes()))",
-/// where the salt consists of 16 random bytes (generated by the operating system), iterations is
-/// the iteration count of the pbkdf and password is the user-supplied password.
+/// The hash is a PHC string of the form "$argon2id$v=19$m=memory,t=iterations,p=parallelism$
+/// base64(salt)$base64(hash)", where the salt consists of 16 random bytes (generated by the
+/// operating system).
 pub fn hash_password(password: &str) -> Result<PasswordHash, PasswordError> {
     let mut salt: [u8; 16] = [0; 16];
     match RANDOMNESS_SOURCE.fill(&mut salt) {
@@ -102,41 +259,78 @@ pub fn hash_password(password: &str) -> Result<PasswordHash, PasswordError> {
         }
     };
 
-    let mut hashed_bytes: [u8; CREDENTIAL_LEN] = [0; CREDENTIAL_LEN];
-
-    pbkdf2::derive(
-        DIGEST_ALG,
-        ITERATIONS,
-        &salt,
-        password.as_bytes(),
-        &mut hashed_bytes,
-    );
-
-    Ok(PasswordHash::new(
-        hashed_bytes.to_vec(),
-        ITERATIONS,
-        salt.to_vec(),
-    ))
+    let config = Argon2Parameters::current().config();
+    let encoded = argon2::hash_encoded(password.as_bytes(), &salt, &config).map_err(|_| {
+        PasswordError::new("Could not hash the password with the configured Argon2 parameters")
+    })?;
+
+    Ok(PasswordHash::Argon2(encoded))
 }
 
 /// Returns a result containing the unit type if the provided password matches the hash, or an error.
 ///
 /// For the unit type to be returned, the provided hash must contain all necessary data to compute
-/// whether or not there is a match, such as the salt, iteration count and hashed password itself.
-/// See `hash_password()` for the format of this hash.
+/// whether or not there is a match, such as the salt, cost parameters and hashed password itself.
+/// See `PasswordHash::from()` for the supported formats.
 pub fn verify_password(password: &str, hash: &PasswordHash) -> Result<(), PasswordError> {
-    match pbkdf2::verify(
-        DIGEST_ALG,
-        hash.iterations,
-        &hash.salt,
-        password.as_bytes(),
-        &hash.hash,
-    ) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(PasswordError::new("Password is incorrect")),
+    let matches = match hash {
+        PasswordHash::Pbkdf2 {
+            hash,
+            iterations,
+            salt,
+        } => pbkdf2::verify(DIGEST_ALG, *iterations, salt, password.as_bytes(), hash).is_ok(),
+        PasswordHash::Argon2(encoded) => argon2::verify_encoded(encoded, password.as_bytes())
+            .map_err(|_| PasswordError::new("The hash is not a valid Argon2 hash"))?,
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(PasswordError::new("Password is incorrect"))
     }
 }
 
+/// Verifies the password sent to login, with the hash of the user it was sent for if they exist.
+///
+/// A dummy hash made with the current scheme is verified when they do not exist, so that the
+/// time a login takes does not reveal whether the user exists.
+pub fn verify_login_password(
+    password: &str,
+    hash: Option<&PasswordHash>,
+) -> Result<(), PasswordError> {
+    match hash {
+        Some(hash) => verify_password(password, hash),
+        None => {
+            let _ = verify_password(password, &DUMMY_ARGON2_HASH);
+            Err(PasswordError::new("Password is incorrect"))
+        }
+    }
+}
+
+/// Makes sure that the user sending a request knows their password, before a sensitive change.
+///
+/// Wrong passwords count as failed login attempts, so that a stolen session cannot be used to
+/// guess the password faster than logins allow, see `login_throttle`.
+pub fn verify_current_password(
+    user: &User,
+    password: &str,
+    ip: Option<IpAddr>,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let now = utils::unix_timestamp();
+    login_throttle::check(&user.email, ip, now, conn)?;
+    let password_hash = PasswordHash::from(&user.password)?;
+    if verify_password(password, &password_hash).is_err() {
+        login_throttle::record_failure(&user.email, ip, now, conn)?;
+        Err(CustomError::new(
+            "Incorrect password".to_string(),
+            Status::Unauthorized,
+        ))?;
+    }
+
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct PasswordError {
     details: String,
@@ -157,3 +351,59 @@ impl fmt::Display for PasswordError {
 }
 
 impl Error for PasswordError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes a hash in the format passwords were stored in before Argon2id.
+    fn legacy_hash(password: &str) -> String {
+        let salt = [7; 16];
+        let mut hash = [0; digest::SHA512_OUTPUT_LEN];
+        pbkdf2::derive(DIGEST_ALG, 1000, &salt, password.as_bytes(), &mut hash);
+
+        format!("{}$1000${}", base64::encode(&salt), base64::encode(&hash[..]))
+    }
+
+    #[test]
+    fn rehashes_legacy_hashes() {
+        let hash = PasswordHash::from(&legacy_hash("secret")).unwrap();
+        assert!(verify_password("secret", &hash).is_ok());
+        assert!(verify_password("other", &hash).is_err());
+        assert!(hash.needs_rehash());
+
+        let stored = PasswordHash::from(&hash.to_string()).unwrap();
+        assert!(stored.to_string().starts_with("$pbkdf2-sha512$i=1000$"));
+        assert!(verify_password("secret", &stored).is_ok());
+        assert!(stored.needs_rehash());
+    }
+
+    #[test]
+    fn keeps_hashes_made_with_the_current_parameters() {
+        let hash = PasswordHash::from(&hash_password("secret").unwrap().to_string()).unwrap();
+        assert!(verify_password("secret", &hash).is_ok());
+        assert!(!hash.needs_rehash());
+    }
+
+    #[test]
+    fn rehashes_hashes_made_with_other_parameters() {
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            mem_cost: 1024,
+            time_cost: 1,
+            ..argon2::Config::default()
+        };
+        let encoded = argon2::hash_encoded(b"secret", &[0; 16], &config).unwrap();
+        let hash = PasswordHash::from(&encoded).unwrap();
+        assert!(verify_password("secret", &hash).is_ok());
+        assert!(hash.needs_rehash());
+    }
+
+    #[test]
+    fn only_verifies_logins_against_the_hash_of_the_user() {
+        let legacy = PasswordHash::from(&legacy_hash("secret")).unwrap();
+        assert!(verify_login_password("secret", Some(&legacy)).is_ok());
+        assert!(verify_login_password("Dummy Password", Some(&legacy)).is_err());
+        assert!(verify_login_password("Dummy Password", None).is_err());
+    }
+}
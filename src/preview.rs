@@ -0,0 +1,119 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::highlight;
+use rocket::http::uri::Uri;
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::Request;
+use std::fs::{self, File};
+use std::io::Cursor;
+use std::path::Path;
+
+/// Text files larger than this are sent as plain text rather than highlighted HTML.
+const HIGHLIGHT_SIZE_LIMIT: u64 = 512 * 1024;
+
+/// Scripts, frames, forms and any resource not served by us are blocked, so that previewing an
+/// HTML or SVG file uploaded by someone else cannot run code on our origin. Every preview is
+/// also sandboxed, PDFs included, as they can embed scripts as well; browsers whose viewer
+/// refuses to run in a sandbox offer to download them instead.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; img-src 'self' data:; \
+                                       media-src 'self'; style-src 'unsafe-inline'";
+
+const HIGHLIGHT_STYLE: &str = "body{margin:0;background:#fdfdfd;color:#24292e}\
+                               pre{margin:0;padding:1em;font:13px/1.5 monospace;white-space:pre-wrap}\
+                               .kw{color:#a626a4}.str{color:#50a14f}.num{color:#986801}\
+                               .com{color:#a0a1a7;font-style:italic}";
+
+enum PreviewBody {
+    Rendered(String),
+    File(File),
+}
+
+/// A file sent so that browsers display it rather than download it.
+pub struct Preview {
+    content_type: ContentType,
+    file_name: String,
+    body: PreviewBody,
+}
+
+impl Preview {
+    /// Prepares a preview of the file at the given path.
+    ///
+    /// Source and text files are rendered as syntax-highlighted HTML, unless they are too large,
+    /// in which case they are sent as plain text. Other files are sent as they are with the
+    /// content type matching their extension.
+    pub fn open(path: &Path) -> Result<Preview, ApiError> {
+        if !path.is_file() {
+            Err(CustomError::new(
+                "Only files can be previewed".to_string(),
+                Status::BadRequest,
+            ))?;
+        }
+
+        let file_name = path
+            .file_name()
+            .ok_or(ApiError::InternalServerError)?
+            .to_string_lossy()
+            .to_string();
+        let extension = match path.extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => file_name.trim_start_matches('.').to_lowercase(),
+        };
+
+        if highlight::is_text_extension(&extension) {
+            if fs::metadata(path)?.len() <= HIGHLIGHT_SIZE_LIMIT {
+                let source = fs::read(path)?;
+                let html = render_source(&file_name, &String::from_utf8_lossy(&source), &extension);
+                return Ok(Preview {
+                    content_type: ContentType::HTML,
+                    file_name,
+                    body: PreviewBody::Rendered(html),
+                });
+            }
+
+            return Ok(Preview {
+                content_type: ContentType::Plain,
+                file_name,
+                body: PreviewBody::File(File::open(path)?),
+            });
+        }
+
+        Ok(Preview {
+            content_type: ContentType::from_extension(&extension).unwrap_or(ContentType::Binary),
+            file_name,
+            body: PreviewBody::File(File::open(path)?),
+        })
+    }
+}
+
+impl<'r> Responder<'r> for Preview {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        let mut response = Response::build();
+        response
+            .header(self.content_type.clone())
+            .raw_header(
+                "Content-Disposition",
+                format!("inline; filename*=UTF-8''{}", Uri::percent_encode(&self.file_name)),
+            )
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .raw_header(
+                "Content-Security-Policy",
+                format!("{}; sandbox", CONTENT_SECURITY_POLICY),
+            );
+
+        match self.body {
+            PreviewBody::Rendered(html) => response.sized_body(Cursor::new(html)),
+            PreviewBody::File(file) => response.sized_body(file),
+        };
+        response.ok()
+    }
+}
+
+fn render_source(file_name: &str, source: &str, extension: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title>\
+         <style>{}</style></head><body><pre><code>{}</code></pre></body></html>",
+        highlight::escape_html(file_name),
+        HIGHLIGHT_STYLE,
+        highlight::highlight(source, extension)
+    )
+}
@@ -0,0 +1,49 @@
+use crate::api_error::{ApiError, CustomError};
+use image::{DynamicImage, ImageOutputFormat, Luma};
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rocket::http::{ContentType, Status};
+use rocket::response::Content;
+
+/// Minimum width and height of rendered codes, in pixels.
+const QR_CODE_SIZE: u32 = 256;
+
+/// A QR code rendered in one of the formats clients can ask for.
+#[derive(Responder)]
+pub enum QrImage {
+    Svg(Content<String>),
+    Png(Content<Vec<u8>>),
+}
+
+/// Renders the given text, usually a URL, as a QR code in the requested format.
+///
+/// Codes are generated locally so that share links are never sent to a third party.
+pub fn render(text: &str, format: &str) -> Result<QrImage, ApiError> {
+    let code = QrCode::new(text.as_bytes())
+        .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+
+    match format {
+        "svg" => {
+            let image = code
+                .render::<svg::Color>()
+                .min_dimensions(QR_CODE_SIZE, QR_CODE_SIZE)
+                .build();
+            Ok(QrImage::Svg(Content(ContentType::SVG, image)))
+        }
+        "png" => {
+            let image = code
+                .render::<Luma<u8>>()
+                .min_dimensions(QR_CODE_SIZE, QR_CODE_SIZE)
+                .build();
+            let mut png = vec![];
+            DynamicImage::ImageLuma8(image)
+                .write_to(&mut png, ImageOutputFormat::Png)
+                .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+            Ok(QrImage::Png(Content(ContentType::PNG, png)))
+        }
+        _ => Err(CustomError::new(
+            "QR codes are only available as svg or png".to_string(),
+            Status::BadRequest,
+        ))?,
+    }
+}
@@ -0,0 +1,279 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::guards::Admin;
+use crate::login_throttle;
+use crate::models::common_models::Message;
+use crate::models::file::{ShareList, ShareResult};
+use crate::models::user::{
+    LockoutList, LockoutResult, PasswordSet, QuotaUpdate, Role, RoleUpdate, ThrottleKind, User,
+    UserDetails, UserDetailsList, UserResult,
+};
+use crate::password_policy;
+use crate::passwords;
+use crate::utils::unix_timestamp;
+use crate::DBConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+/// List every user, along with the space their files take
+#[get("/users")]
+pub fn list_users(_admin: Admin, conn: DBConnection) -> Result<Json<UserDetailsList>, ApiError> {
+    let users = db::user::get_all(&conn)?.iter().map(user_details).collect();
+
+    Ok(Json(UserDetailsList { users }))
+}
+
+#[get("/users/<id>")]
+pub fn get_user(id: i32, _admin: Admin, conn: DBConnection) -> Result<Json<UserDetails>, ApiError> {
+    let user = find_user(id, &conn)?;
+
+    Ok(Json(user_details(&user)))
+}
+
+/// Make a user an administrator, or a regular user again
+///
+/// Users listed in `ADMIN_EMAILS` stay administrators whatever their role, and administrators
+/// cannot demote themselves, so that the server always keeps one.
+#[put("/users/<id>/role", data = "<update>")]
+pub fn set_role(
+    id: i32,
+    update: Json<RoleUpdate>,
+    admin: Admin,
+    conn: DBConnection,
+) -> Result<Json<UserDetails>, ApiError> {
+    let user = find_user(id, &conn)?;
+    if update.role != Role::Admin {
+        if user.id == admin.0.id {
+            Err(CustomError::new(
+                "You cannot remove your own administrator role".to_string(),
+                Status::Conflict,
+            ))?;
+        }
+        if user.is_configured_admin() {
+            Err(CustomError::new(
+                "This user is an administrator through ADMIN_EMAILS".to_string(),
+                Status::Conflict,
+            ))?;
+        }
+    }
+    db::user::set_role(user.id, update.role.as_str(), &conn)?;
+    info!(
+        "{} was given the {} role by {}",
+        user.email,
+        update.role.as_str(),
+        admin.0.email
+    );
+
+    let user = find_user(id, &conn)?;
+    Ok(Json(user_details(&user)))
+}
+
+/// Prevent a user from logging in, and log out all their sessions
+///
+/// The API tokens of disabled users are refused as well, and their shares keep working.
+#[post("/users/<id>/disable")]
+pub fn disable_user(id: i32, admin: Admin, conn: DBConnection) -> Result<Json<Message>, ApiError> {
+    let user = find_user(id, &conn)?;
+    if user.id == admin.0.id {
+        Err(CustomError::new(
+            "You cannot disable your own account".to_string(),
+            Status::Conflict,
+        ))?;
+    }
+    db::user::set_disabled(user.id, true, &conn)?;
+    db::session::delete_user_sessions(user.id, &conn)?;
+    info!("{} was disabled by {}", user.email, admin.0.email);
+
+    Ok(Json(Message {
+        message: "User disabled successfully".to_string(),
+    }))
+}
+
+/// Let a disabled user login again
+#[post("/users/<id>/enable")]
+pub fn enable_user(id: i32, admin: Admin, conn: DBConnection) -> Result<Json<Message>, ApiError> {
+    let user = find_user(id, &conn)?;
+    db::user::set_disabled(user.id, false, &conn)?;
+    info!("{} was enabled by {}", user.email, admin.0.email);
+
+    Ok(Json(Message {
+        message: "User enabled successfully".to_string(),
+    }))
+}
+
+/// Choose a new password for a user, which logs out all their sessions and revokes their API
+/// tokens
+///
+/// The password must follow the same policy as the ones chosen by users.
+#[put("/users/<id>/password", data = "<password>")]
+pub fn set_password(
+    id: i32,
+    password: Json<PasswordSet>,
+    admin: Admin,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    let user = find_user(id, &conn)?;
+    password_policy::validate(
+        "new_password",
+        &password.new_password,
+        &[&user.email, &user.display_name],
+    )?;
+
+    let password_hash = passwords::hash_password(&password.new_password)?.to_string();
+    db::user::update_password(user.id, &password_hash, &conn)?;
+    db::session::delete_user_sessions(user.id, &conn)?;
+    db::token::delete_user_tokens(user.id, &conn)?;
+    info!(
+        "The password of {} was reset by {}",
+        user.email, admin.0.email
+    );
+
+    Ok(Json(Message {
+        message: "Password reset successfully".to_string(),
+    }))
+}
+
+/// Limit the number of bytes the files of a user can take, or remove the limit with a `null`
+/// quota
+///
+/// Files which are already stored are kept when they exceed the new quota, but no more files
+/// can be uploaded until enough of them are deleted.
+#[put("/users/<id>/quota", data = "<update>")]
+pub fn set_quota(
+    id: i32,
+    update: Json<QuotaUpdate>,
+    admin: Admin,
+    conn: DBConnection,
+) -> Result<Json<UserDetails>, ApiError> {
+    let user = find_user(id, &conn)?;
+    let quota = update.quota.map(|quota| quota.min(i64::MAX as u64) as i64);
+    db::user::set_quota(user.id, quota, &conn)?;
+    match quota {
+        Some(quota) => info!(
+            "The quota of {} was set to {} bytes by {}",
+            user.email, quota, admin.0.email
+        ),
+        None => info!(
+            "The quota of {} was removed by {}",
+            user.email, admin.0.email
+        ),
+    }
+
+    let user = find_user(id, &conn)?;
+    Ok(Json(user_details(&user)))
+}
+
+/// Log out every session of a user
+///
+/// Their API tokens keep working, users can revoke them from their account.
+#[delete("/users/<id>/sessions")]
+pub fn logout_user(id: i32, admin: Admin, conn: DBConnection) -> Result<Json<Message>, ApiError> {
+    let user = find_user(id, &conn)?;
+    let deleted = db::session::delete_user_sessions(user.id, &conn)?;
+    info!(
+        "{} sessions of {} were logged out by {}",
+        deleted, user.email, admin.0.email
+    );
+
+    Ok(Json(Message {
+        message: format!("{} sessions logged out", deleted),
+    }))
+}
+
+/// List the accounts and client addresses which cannot login, and the shares which cannot be
+/// unlocked, because of failed attempts
+#[get("/lockouts")]
+pub fn list_lockouts(_admin: Admin, conn: DBConnection) -> Result<Json<LockoutList>, ApiError> {
+    let lockouts = db::throttle::get_locked(unix_timestamp(), &conn)?
+        .into_iter()
+        .filter_map(|throttle| {
+            Some(LockoutResult {
+                kind: ThrottleKind::parse(&throttle.kind)?,
+                value: throttle.value,
+                failures: throttle.failures,
+                last_failure: throttle.last_failure,
+                locked_until: throttle.locked_until?,
+            })
+        })
+        .collect();
+
+    Ok(Json(LockoutList { lockouts }))
+}
+
+/// Let an account (identified by its email address) or a client address login again, or a
+/// share be unlocked again, and forget its failed attempts
+#[delete("/lockouts/<kind>/<value>")]
+pub fn unlock(
+    kind: String,
+    value: String,
+    admin: Admin,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    let kind = ThrottleKind::parse(&kind).ok_or(ApiError::NotFound)?;
+    let value = match kind {
+        ThrottleKind::Account => login_throttle::account_key(&value),
+        ThrottleKind::Ip | ThrottleKind::Share => value,
+    };
+    if !db::throttle::delete_throttle(kind.as_str(), &value, &conn)? {
+        Err(CustomError::new(
+            "There were no failed attempts for this account, address or share".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+    info!(
+        "Failed attempts for {} {} were forgotten by {}",
+        kind.as_str(),
+        value,
+        admin.0.email
+    );
+
+    Ok(Json(Message {
+        message: "Logins unlocked successfully".to_string(),
+    }))
+}
+
+/// List the shares created before their owner was recorded, whose path could not be converted
+/// to a file of a user
+#[get("/shares/unowned")]
+pub fn list_unowned_shares(_admin: Admin, conn: DBConnection) -> Result<Json<ShareList>, ApiError> {
+    let shares = db::file::get_unowned_shares(&conn)?
+        .iter()
+        .map(|share| ShareResult::from(share, None))
+        .collect();
+
+    Ok(Json(ShareList { shares }))
+}
+
+/// Revoke a share which has no owner
+#[delete("/shares/unowned/<link>")]
+pub fn revoke_unowned_share(
+    link: String,
+    admin: Admin,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    if !db::file::delete_user_share(&link, None, &conn)? {
+        Err(CustomError::new(
+            "This share does not exist or has an owner".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+    info!("Share {} was revoked by {}", link, admin.0.email);
+
+    Ok(Json(Message {
+        message: "Share revoked successfully".to_string(),
+    }))
+}
+
+fn find_user(id: i32, conn: &DBConnection) -> Result<User, ApiError> {
+    db::user::get_by_id(id, conn)?.ok_or(ApiError::NotFound)
+}
+
+fn user_details(user: &User) -> UserDetails {
+    UserDetails {
+        user: UserResult::from(user),
+        role: user.role(),
+        disabled: user.disabled,
+        quota: user.quota,
+        used_bytes: user.used_bytes.max(0) as u64,
+    }
+}
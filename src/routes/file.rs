@@ -1,24 +1,51 @@
+use crate::access;
 use crate::api_error::{ApiError, CustomError};
 use crate::db;
+use crate::email_verification;
+use crate::guards::{BaseUrl, ClientInfo, ResponseFormat};
+use crate::login_throttle;
 use crate::models::common_models::Message;
 use crate::models::file::{
-    DirContents, FileSystemElement, FileSystemElementType, JsonPath, PendingUpload, Share, UploadID,
+    DirContents, DropFile, FileMove, FileSystemElement, FileSystemElementType, JsonPath,
+    NewShareAccess, PendingUpload, Share, ShareAccessLog, ShareAccessResult, ShareCreate,
+    ShareKind, ShareList, ShareResult, ShareStats, ShareUnlock, SharedContent, UploadID, Uploader,
 };
+use crate::models::grant::Permission;
 use crate::models::user::User;
-use crate::utils;
+use crate::pages;
+use crate::passwords;
+use crate::preview::Preview;
+use crate::qr::{self, QrImage};
+use crate::share_log::Logged;
+use crate::thumbnails::{self, Thumbnail, ThumbnailFormat};
+use crate::tokens;
+use crate::utils::{self, Namespace};
 use crate::DBConnection;
-use crate::PendingUploadStore;
+use crate::{ShareLogQueue, ThumbnailQueue};
+use diesel::Connection;
 use rocket::data::Data;
-use rocket::http::Status;
-use rocket::response::NamedFile;
+use rocket::http::uri::Uri;
+use rocket::http::{Cookie, Cookies, Status};
+use rocket::request::Form;
+use rocket::response::content::Html;
+use rocket::response::{NamedFile, Redirect};
 use rocket::State;
 use rocket_contrib::json::Json;
 use std::fs;
-use std::path::Path;
-use std::time::Instant;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
 use tempfile::tempdir;
 use uuid::Uuid;
 
+/// Number of seconds during which a password-protected share stays accessible once unlocked
+const SHARE_UNLOCK_DURATION: i64 = 60 * 60;
+/// User agents are truncated to this number of characters in the access log of shares
+const MAX_LOGGED_USER_AGENT_LENGTH: usize = 512;
+const DEFAULT_SHARE_LOG_LENGTH: u32 = 50;
+const MAX_SHARE_LOG_LENGTH: u32 = 500;
+const MIN_SLUG_LENGTH: usize = 3;
+const MAX_SLUG_LENGTH: usize = 64;
+
 /// Prepare a new file upload to the server
 ///
 /// This is needed because saving the entire multipart data to a temporary
@@ -26,16 +53,23 @@ use uuid::Uuid;
 /// path is undesirable. This route returns an upload ID which can be used
 /// to upload a file at a given path.
 ///
-/// Paths must be relative to the root of the user's content storage directory.
-/// Absolute paths are rejected. Paths may not contain references to the parent
-/// directory. Paths must also point to a file and not a directory.
+/// Paths must be relative to the root of the user's content storage directory,
+/// to the file or directory shared with the user when a `grant` ID is given, or
+/// to the directory of a group when a `group` ID is given, which also applies to
+/// the other routes taking a path. Absolute paths are rejected. Paths may not
+/// contain references to the parent directory. Paths must also point to a file
+/// and not a directory. Uploads fail once the quota of the user or group owning the files is
+/// reached, and uploads by users who did not verify their email address fail when
+/// `REQUIRE_EMAIL_VERIFICATION` is set.
 #[post("/upload/new", data = "<path>")]
 pub fn new_upload(
     path: Json<JsonPath>,
     user: User,
-    pending_uploads: State<PendingUploadStore>,
+    conn: DBConnection,
 ) -> Result<Json<UploadID>, ApiError> {
-    let path = utils::user_root_path(&user)?.join(path.into_inner().to_pathbuf()?);
+    email_verification::ensure_verified(&user)?;
+    let (namespace, path) =
+        access::resolve_with_namespace(path.into_inner(), &user, Permission::Write, &conn)?;
     if path.is_dir() {
         Err(CustomError::new(
             "Paths must point to a file".to_string(),
@@ -44,12 +78,9 @@ pub fn new_upload(
     }
 
     let upload_id = Uuid::new_v4();
-    let pending_upload = PendingUpload {
-        path,
-        user,
-        created: Instant::now(),
-    };
-    pending_uploads.write().insert(upload_id, pending_upload);
+    let pending_upload =
+        PendingUpload::new(upload_id, &path, Uploader::User(user.id), Some(namespace))?;
+    db::file::save_pending_upload(&pending_upload, &conn)?;
 
     Ok(Json(UploadID { upload_id }))
 }
@@ -59,37 +90,74 @@ pub fn upload(
     id: String,
     user: User,
     file: Data,
-    pending_uploads_lock: State<PendingUploadStore>,
+    conn: DBConnection,
+    thumbnail_queue: State<ThumbnailQueue>,
 ) -> Result<Json<Message>, ApiError> {
     let parsed_id = Uuid::parse_str(&id)
         .map_err(|_| CustomError::new("Invalid upload ID".to_string(), Status::BadRequest))?;
-    let pending_uploads = pending_uploads_lock.read();
-    let associated_upload = pending_uploads
-        .get(&parsed_id)
+    let associated_upload = db::file::get_pending_upload(&parsed_id.to_string(), &conn)?
         .ok_or_else(|| CustomError::new("Upload ID not in use".to_string(), Status::BadRequest))?;
-    if associated_upload.user != user {
+    if associated_upload.uploader() != Some(Uploader::User(user.id)) {
         Err(CustomError::new(
             "A different user created this upload".to_string(),
             Status::Unauthorized,
         ))?;
     }
+    access::check_pending_upload(&associated_upload, &user, &conn)?;
 
-    let str_path = associated_upload
-        .path
-        .to_str()
-        .ok_or_else(|| ApiError::InternalServerError)?
-        .to_string();
+    let upload_path = PathBuf::from(&associated_upload.path);
+    let parent = upload_path.parent().ok_or(ApiError::InternalServerError)?;
+    fs::create_dir_all(parent)
+        .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
 
-    if let Some(path) = associated_upload.path.parent() {
-        fs::create_dir_all(path)
-            .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
-    }
-    drop(pending_uploads); // Release the lock explicitly before streaming the file to disk
-    file.stream_to_file(str_path)
+    // The data is written next to the destination first, so that a failed upload never
+    // replaces or removes an existing file
+    let mut temp_file = tempfile::Builder::new()
+        .prefix(".upload-")
+        .tempfile_in(parent)
+        .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+    // Replacing a file frees the space it took
+    let replaced_bytes = match fs::metadata(&upload_path) {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => 0,
+    };
+    let namespace = associated_upload.namespace();
+    let remaining_space = match namespace {
+        Some(namespace) => access::remaining_space(namespace, &conn)?
+            .map(|remaining_space| remaining_space.saturating_add(replaced_bytes)),
+        None => None,
+    };
+    let size_limit = access::size_limit(&[remaining_space]);
+    // Read one byte past the limit to find out whether the file is too large
+    let written = io::copy(&mut file.open().take(size_limit + 1), &mut temp_file)
         .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+    let added_bytes = written as i64 - replaced_bytes as i64;
+    // The quota is checked again when counting the file, as other uploads may have used the
+    // space left in the meantime
+    let fits = written <= size_limit
+        && match namespace {
+            Some(namespace) => access::record_usage(namespace, added_bytes, &conn)?,
+            None => true,
+        };
+    if !fits {
+        let message = match namespace {
+            Some(Namespace::Group(_)) => "This file is larger than the space left in this group",
+            _ => "This file is larger than the space left for these files",
+        };
+        Err(CustomError::new(
+            message.to_string(),
+            Status::PayloadTooLarge,
+        ))?;
+    }
+    if let Err(e) = temp_file.persist(&upload_path) {
+        if let Some(namespace) = namespace {
+            access::record_usage(namespace, -added_bytes, &conn)?;
+        }
+        Err(CustomError::new(e.to_string(), Status::InternalServerError))?;
+    }
 
-    let mut pending_uploads = pending_uploads_lock.write();
-    pending_uploads.remove(&parsed_id);
+    db::file::delete_pending_upload(&associated_upload.id, &conn)?;
+    thumbnails::enqueue(&thumbnail_queue, &upload_path);
 
     Ok(Json(Message {
         message: "Upload successful".to_string(),
@@ -97,36 +165,23 @@ pub fn upload(
 }
 
 #[post("/ls", data = "<path>")]
-pub fn ls(path: Json<JsonPath>, user: User) -> Result<Json<DirContents>, ApiError> {
-    let path = utils::user_root_path(&user)?.join(path.into_inner().to_pathbuf()?);
-    let mut contents = vec![];
-    let entries =
-        fs::read_dir(path).map_err(|e| CustomError::new(e.to_string(), Status::BadRequest))?;
-    for entry in entries {
-        let entry = entry?;
-        let metadata = entry.metadata()?;
-
-        let (fs_element_type, fs_element_size) = {
-            if metadata.is_dir() {
-                (FileSystemElementType::Directory, 0)
-            } else {
-                (FileSystemElementType::File, metadata.len())
-            }
-        };
-        let fs_element = FileSystemElement {
-            element_type: fs_element_type,
-            name: entry.file_name().to_string_lossy().to_string(),
-            bytes: fs_element_size,
-        };
-        contents.push(fs_element);
-    }
+pub fn ls(
+    path: Json<JsonPath>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<DirContents>, ApiError> {
+    let path = access::resolve(path.into_inner(), &user, Permission::Read, &conn)?;
 
-    Ok(Json(DirContents { contents }))
+    Ok(Json(list_directory(&path)?))
 }
 
 #[post("/mkdir", data = "<path>")]
-pub fn mkdir(path: Json<JsonPath>, user: User) -> Result<Json<Message>, ApiError> {
-    let path = utils::user_root_path(&user)?.join(path.into_inner().to_pathbuf()?);
+pub fn mkdir(
+    path: Json<JsonPath>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    let path = access::resolve(path.into_inner(), &user, Permission::Write, &conn)?;
 
     fs::create_dir_all(path)
         .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
@@ -136,54 +191,973 @@ pub fn mkdir(path: Json<JsonPath>, user: User) -> Result<Json<Message>, ApiError
     }))
 }
 
+/// Move or rename a file or directory
+///
+/// The `destination` is relative to the same directory as `path`, so files can
+/// only be moved within a user's directory, a grant or a group. Shares and
+/// grants of the moved files, or of files inside moved directories, keep
+/// working after the move.
+#[post("/move", data = "<file_move>")]
+pub fn move_file(
+    file_move: Json<FileMove>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    let file_move = file_move.into_inner();
+    if Path::new(&file_move.path.path)
+        .components()
+        .all(|c| c == Component::CurDir)
+    {
+        Err(CustomError::new(
+            "The root of a directory cannot be moved".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+    let destination = JsonPath {
+        path: file_move.destination,
+        grant: file_move.path.grant,
+        group: file_move.path.group,
+    };
+    let (namespace, source) =
+        access::resolve_with_namespace(file_move.path, &user, Permission::Write, &conn)?;
+    let destination = access::resolve(destination, &user, Permission::Write, &conn)?;
+
+    if !source.exists() {
+        Err(CustomError::new(
+            "This path does not exist".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+    if destination.exists() {
+        Err(CustomError::new(
+            "Something already exists at the destination".to_string(),
+            Status::Conflict,
+        ))?;
+    }
+    if destination.starts_with(&source) {
+        Err(CustomError::new(
+            "Directories cannot be moved inside themselves".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    // Update the references first, so that they are rolled back if the move fails
+    conn.transaction::<_, ApiError, _>(|| {
+        if let Namespace::User(owner_id) = namespace {
+            let root = utils::root_path(namespace);
+            let from = relative_path_str(&source, &root)?;
+            let to = relative_path_str(&destination, &root)?;
+            db::file::record_file_move(owner_id, from, to, &conn)?;
+            db::grant::record_grant_move(owner_id, from, to, &conn)?;
+        }
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&source, &destination)
+            .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+
+        Ok(())
+    })?;
+
+    Ok(Json(Message {
+        message: "Moved successfully".to_string(),
+    }))
+}
+
 #[post("/download", data = "<path>")]
-pub fn download(path: Json<JsonPath>, user: User) -> Result<NamedFile, ApiError> {
-    let path = utils::user_root_path(&user)?.join(path.into_inner().to_pathbuf()?);
+pub fn download(
+    path: Json<JsonPath>,
+    user: User,
+    conn: DBConnection,
+) -> Result<NamedFile, ApiError> {
+    let path = access::resolve(path.into_inner(), &user, Permission::Read, &conn)?;
 
     get_named_file(&path)
 }
 
-#[post("/share", data = "<path>")]
-pub fn create_share(
+/// Get a file so that it can be displayed by the browser rather than downloaded
+///
+/// Text and source files are rendered as syntax-highlighted HTML. Every preview
+/// is sandboxed so that uploaded HTML or SVG documents cannot run scripts.
+#[post("/preview", data = "<path>")]
+pub fn preview(path: Json<JsonPath>, user: User, conn: DBConnection) -> Result<Preview, ApiError> {
+    let path = access::resolve(path.into_inner(), &user, Permission::Read, &conn)?;
+
+    Preview::open(&path)
+}
+
+/// Get a small preview of an image, as WebP when the client accepts it or as JPEG otherwise
+///
+/// Thumbnails are cached and only regenerated when the image changes. Images
+/// uploaded through `upload` have their thumbnail generated in the background,
+/// so it is usually ready by the time it is requested.
+#[post("/thumbnail", data = "<path>")]
+pub fn thumbnail(
     path: Json<JsonPath>,
     user: User,
+    format: ThumbnailFormat,
+    conn: DBConnection,
+) -> Result<Thumbnail, ApiError> {
+    let path = access::resolve(path.into_inner(), &user, Permission::Read, &conn)?;
+
+    get_thumbnail(&path, format)
+}
+
+/// Create a public link to a file or directory
+///
+/// An optional `expires_in` number of seconds can be given, after which the
+/// link stops working and is eventually deleted. An optional `password` can
+/// also be given, in which case the link must be unlocked before the shared
+/// content can be accessed. Finally, `max_downloads` limits the number of times
+/// the shared content can be downloaded or previewed.
+///
+/// When `kind` is `drop`, the link points to a directory to which anyone with
+/// the link can upload files, without being able to see what it contains. The
+/// size of those uploads can be limited with `max_file_size` and `max_total_size`.
+///
+/// A `slug` made of lowercase letters, digits and hyphens can be given to use
+/// as the link instead of a random ID, as long as no other share uses it.
+#[post("/share", data = "<share>")]
+pub fn create_share(
+    share: Json<ShareCreate>,
+    user: User,
     conn: DBConnection,
-) -> Result<Json<Share>, ApiError> {
-    let user_prefix = utils::user_root_path(&user)?;
-    let user_path = path.into_inner().to_pathbuf()?;
-    let full_path = user_prefix.join(&user_path);
+) -> Result<Json<ShareResult>, ApiError> {
+    let share = share.into_inner();
+    let user_prefix = utils::root_path(Namespace::User(user.id));
+    let full_path = access::own_path(share.path, &user)?;
 
-    let share = Share {
-        link: Uuid::new_v4().to_string(),
-        path: full_path
-            .to_str()
-            .ok_or_else(|| ApiError::InternalServerError)?
-            .to_string(),
+    let expires_at = match share.expires_in {
+        Some(0) => Err(CustomError::new(
+            "Shares must not expire immediately".to_string(),
+            Status::BadRequest,
+        ))?,
+        Some(seconds) => Some(utils::timestamp_after(utils::unix_timestamp(), seconds)),
+        None => None,
+    };
+    let password = match share.password {
+        Some(password) if password.is_empty() => Err(CustomError::new(
+            "Share passwords must not be empty".to_string(),
+            Status::BadRequest,
+        ))?,
+        Some(password) => Some(passwords::hash_password(&password)?.to_string()),
+        None => None,
     };
-    db::file::save_share(&share, &conn)?;
+    let remaining_downloads = match share.max_downloads {
+        Some(0) => Err(CustomError::new(
+            "Shares must allow at least one download".to_string(),
+            Status::BadRequest,
+        ))?,
+        Some(max_downloads) => Some(max_downloads.min(i32::MAX as u32) as i32),
+        None => None,
+    };
+
+    if share.kind == ShareKind::Drop {
+        if remaining_downloads.is_some() {
+            Err(CustomError::new(
+                "File drops cannot limit the number of downloads".to_string(),
+                Status::BadRequest,
+            ))?;
+        }
+        if full_path.is_file() {
+            Err(CustomError::new(
+                "File drops must point to a directory".to_string(),
+                Status::BadRequest,
+            ))?;
+        }
+        fs::create_dir_all(&full_path)?;
+    } else if share.max_file_size.is_some() || share.max_total_size.is_some() {
+        Err(CustomError::new(
+            "Only file drops can limit the size of uploads".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    let user_path = full_path
+        .strip_prefix(&user_prefix)
+        .map_err(|_| ApiError::InternalServerError)?
+        .to_str()
+        .ok_or(ApiError::InternalServerError)?;
+
+    let link = match share.slug {
+        Some(slug) => {
+            validate_slug(&slug)?;
+            slug
+        }
+        None => Uuid::new_v4().to_string(),
+    };
+
+    let mut share = Share {
+        link,
+        file_id: None,
+        expires_at,
+        password,
+        remaining_downloads,
+        user_id: Some(user.id),
+        created_at: utils::unix_timestamp(),
+        kind: share.kind.as_str().to_string(),
+        max_file_size: share
+            .max_file_size
+            .map(|size| size.min(i64::MAX as u64) as i64),
+        max_total_size: share
+            .max_total_size
+            .map(|size| size.min(i64::MAX as u64) as i64),
+        uploaded_bytes: 0,
+        legacy_path: None,
+    };
+    // Only track the file if the share pointing to it is saved as well
+    let file = conn.transaction::<_, ApiError, _>(|| {
+        let file = db::file::track_file(user.id, user_path, &conn)?;
+        share.file_id = Some(file.id);
+        db::file::save_share(&share, &conn)?;
+
+        Ok(file)
+    })?;
+
+    Ok(Json(ShareResult::from(&share, Some(&file))))
+}
+
+/// List the shares created by the current user, most recent first
+#[get("/shares")]
+pub fn list_shares(user: User, conn: DBConnection) -> Result<Json<ShareList>, ApiError> {
+    let shares = db::file::get_user_shares(user.id, &conn)?
+        .iter()
+        .map(|(share, file)| ShareResult::from(share, file.as_ref()))
+        .collect();
+
+    Ok(Json(ShareList { shares }))
+}
 
-    // Return just the user path to avoid revealing the user's ID
-    let returned_share = Share {
-        link: share.link.clone(),
-        path: user_path
-            .to_str()
-            .ok_or_else(|| ApiError::InternalServerError)?
-            .to_string(),
+#[get("/shares/<link>")]
+pub fn inspect_share(
+    link: String,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<ShareResult>, ApiError> {
+    let share = find_own_share(&link, &user, &conn)?;
+    let file = match share.file_id {
+        Some(file_id) => db::file::get_tracked_file(file_id, &conn)?,
+        None => None,
     };
-    Ok(Json(returned_share))
+
+    Ok(Json(ShareResult::from(&share, file.as_ref())))
+}
+
+/// Get statistics about the accesses to a share
+///
+/// Accesses are only kept for `SHARE_LOG_RETENTION_DAYS` days (90 by default),
+/// so the statistics only cover that period.
+#[get("/shares/<link>/stats")]
+pub fn share_stats(
+    link: String,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<ShareStats>, ApiError> {
+    let share = find_own_share(&link, &user, &conn)?;
+    let summary = db::file::get_share_stats(&share.link, &conn)?;
+
+    Ok(Json(ShareStats::from(&summary)))
+}
+
+/// Get the most recent accesses to a share, 50 by default and at most 500
+#[get("/shares/<link>/log?<limit>")]
+pub fn share_log(
+    link: String,
+    limit: Option<u32>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<ShareAccessLog>, ApiError> {
+    let share = find_own_share(&link, &user, &conn)?;
+    let limit = limit
+        .unwrap_or(DEFAULT_SHARE_LOG_LENGTH)
+        .min(MAX_SHARE_LOG_LENGTH);
+    let accesses = db::file::get_share_accesses(&share.link, Some(limit as i64), &conn)?
+        .iter()
+        .map(ShareAccessResult::from)
+        .collect();
+
+    Ok(Json(ShareAccessLog { accesses }))
+}
+
+/// Get the link to a share as a QR code, in the `svg` (default) or `png` format
+#[get("/shares/<link>/qr?<format>")]
+pub fn share_qr_code(
+    link: String,
+    format: Option<String>,
+    base_url: BaseUrl,
+    user: User,
+    conn: DBConnection,
+) -> Result<QrImage, ApiError> {
+    let share = find_own_share(&link, &user, &conn)?;
+    let url = format!(
+        "{}/file/shared/{}",
+        base_url.0,
+        Uri::percent_encode(&share.link)
+    );
+
+    qr::render(&url, format.as_deref().unwrap_or("svg"))
+}
+
+/// Revoke a share, after which its link stops working immediately
+#[delete("/shares/<link>")]
+pub fn revoke_share(
+    link: String,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    if !db::file::delete_user_share(&link, Some(user.id), &conn)? {
+        Err(CustomError::new(
+            "This share does not exist".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+
+    Ok(Json(Message {
+        message: "Share revoked successfully".to_string(),
+    }))
 }
 
+/// Get the content of a share
+///
+/// Shared files are downloaded directly. Shared directories are rendered as a
+/// page which can be browsed when the client prefers HTML, listed when the
+/// client prefers JSON, and downloaded as a zip file otherwise. Every request
+/// is recorded in the access log of the share.
 #[get("/shared/<id>")]
-pub fn download_shared(id: String, conn: DBConnection) -> Result<NamedFile, ApiError> {
-    let share = db::file::get_share(&id, &conn)?.ok_or_else(|| {
+pub fn download_shared(
+    id: String,
+    format: ResponseFormat,
+    client: ClientInfo,
+    conn: DBConnection,
+    mut cookies: Cookies,
+    share_log_queue: State<ShareLogQueue>,
+) -> Result<Logged<SharedContent>, ApiError> {
+    let content = shared_content(&id, format, &conn, &mut cookies);
+
+    record_access(&id, &client, content, &conn, &share_log_queue)
+}
+
+#[get("/shared/<id>/download")]
+pub fn download_shared_root(
+    id: String,
+    client: ClientInfo,
+    conn: DBConnection,
+    mut cookies: Cookies,
+    share_log_queue: State<ShareLogQueue>,
+) -> Result<Logged<NamedFile>, ApiError> {
+    let file = find_unlocked_share(&id, &conn, &mut cookies).and_then(|(share, shared_path)| {
+        consume_download(&share, &conn, || get_named_file(&shared_path))
+    });
+
+    record_access(&id, &client, file, &conn, &share_log_queue)
+}
+
+/// Download a file or a subdirectory (as a zip file) from a shared directory
+#[get("/shared/<id>/download/<path..>")]
+pub fn download_shared_path(
+    id: String,
+    path: PathBuf,
+    client: ClientInfo,
+    conn: DBConnection,
+    mut cookies: Cookies,
+    share_log_queue: State<ShareLogQueue>,
+) -> Result<Logged<NamedFile>, ApiError> {
+    let file = find_unlocked_share(&id, &conn, &mut cookies).and_then(|(share, shared_path)| {
+        let path = utils::resolve_within(&shared_path, &path)?;
+        consume_download(&share, &conn, || get_named_file(&path))
+    });
+
+    record_access(&id, &client, file, &conn, &share_log_queue)
+}
+
+/// List a subdirectory of a shared directory, as a page or as JSON
+#[get("/shared/<id>/browse/<path..>")]
+pub fn browse_shared(
+    id: String,
+    path: PathBuf,
+    format: ResponseFormat,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<SharedContent, ApiError> {
+    let (share, shared_path) = find_unlocked_share(&id, &conn, &mut cookies)?;
+
+    browse(&share, &shared_path, &path, format)
+}
+
+#[get("/shared/<id>/preview")]
+pub fn shared_preview(
+    id: String,
+    client: ClientInfo,
+    conn: DBConnection,
+    mut cookies: Cookies,
+    share_log_queue: State<ShareLogQueue>,
+) -> Result<Logged<Preview>, ApiError> {
+    let preview = find_unlocked_share(&id, &conn, &mut cookies).and_then(|(share, shared_path)| {
+        consume_download(&share, &conn, || Preview::open(&shared_path))
+    });
+
+    record_access(&id, &client, preview, &conn, &share_log_queue)
+}
+
+#[get("/shared/<id>/preview/<path..>")]
+pub fn shared_path_preview(
+    id: String,
+    path: PathBuf,
+    client: ClientInfo,
+    conn: DBConnection,
+    mut cookies: Cookies,
+    share_log_queue: State<ShareLogQueue>,
+) -> Result<Logged<Preview>, ApiError> {
+    let preview = find_unlocked_share(&id, &conn, &mut cookies).and_then(|(share, shared_path)| {
+        let path = utils::resolve_within(&shared_path, &path)?;
+        consume_download(&share, &conn, || Preview::open(&path))
+    });
+
+    record_access(&id, &client, preview, &conn, &share_log_queue)
+}
+
+#[get("/shared/<id>/thumbnail")]
+pub fn shared_thumbnail(
+    id: String,
+    format: ThumbnailFormat,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Thumbnail, ApiError> {
+    let (_, shared_path) = find_unlocked_share(&id, &conn, &mut cookies)?;
+
+    get_thumbnail(&shared_path, format)
+}
+
+#[get("/shared/<id>/thumbnail/<path..>")]
+pub fn shared_path_thumbnail(
+    id: String,
+    path: PathBuf,
+    format: ThumbnailFormat,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Thumbnail, ApiError> {
+    let (_, shared_path) = find_unlocked_share(&id, &conn, &mut cookies)?;
+    let path = utils::resolve_within(&shared_path, &path)?;
+
+    get_thumbnail(&path, format)
+}
+
+/// Prepare an anonymous upload to a file drop
+///
+/// This works like `new_upload`, except that only a file name is given. The
+/// file is stored in the shared directory, with a number added to its name if
+/// another file already has it.
+#[post("/shared/<id>/upload/new", data = "<file>")]
+pub fn new_drop_upload(
+    id: String,
+    file: Json<DropFile>,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Json<UploadID>, ApiError> {
+    let (share, shared_path) = find_unlocked_drop(&id, &conn, &mut cookies)?;
+    let mut name_components = Path::new(&file.name).components();
+    let name = match (name_components.next(), name_components.next()) {
+        (Some(Component::Normal(name)), None) => name,
+        _ => Err(CustomError::new(
+            "File names must not contain a path".to_string(),
+            Status::BadRequest,
+        ))?,
+    };
+
+    let upload_id = Uuid::new_v4();
+    let pending_upload = PendingUpload::new(
+        upload_id,
+        &shared_path.join(name),
+        Uploader::Share(share.link),
+        None,
+    )?;
+    db::file::save_pending_upload(&pending_upload, &conn)?;
+
+    Ok(Json(UploadID { upload_id }))
+}
+
+#[post("/shared/<id>/upload/<upload_id>", data = "<file>", rank = 2)]
+pub fn drop_upload(
+    id: String,
+    upload_id: String,
+    file: Data,
+    conn: DBConnection,
+    mut cookies: Cookies,
+    thumbnail_queue: State<ThumbnailQueue>,
+) -> Result<Json<Message>, ApiError> {
+    let (share, _) = find_unlocked_drop(&id, &conn, &mut cookies)?;
+    let parsed_id = Uuid::parse_str(&upload_id)
+        .map_err(|_| CustomError::new("Invalid upload ID".to_string(), Status::BadRequest))?;
+    let associated_upload = db::file::get_pending_upload(&parsed_id.to_string(), &conn)?
+        .ok_or_else(|| CustomError::new("Upload ID not in use".to_string(), Status::BadRequest))?;
+    if associated_upload.uploader() != Some(Uploader::Share(share.link.clone())) {
+        Err(CustomError::new(
+            "This upload was created for a different share".to_string(),
+            Status::Unauthorized,
+        ))?;
+    }
+
+    let (mut destination, upload_path) =
+        utils::create_unique_file(Path::new(&associated_upload.path))?;
+
+    let remaining_space = share
+        .max_total_size
+        .map(|max_total_size| access::space_left(max_total_size, share.uploaded_bytes));
+    // Files dropped by visitors count against the quota of the owner of the share
+    let owner_space = match share.user_id {
+        Some(user_id) => access::remaining_space(Namespace::User(user_id), &conn)?,
+        None => None,
+    };
+    let size_limit = access::size_limit(&[
+        share.max_file_size.map(|size| size as u64),
+        remaining_space,
+        owner_space,
+    ]);
+    // Read one byte past the limit to find out whether the file is too large
+    let written = io::copy(&mut file.open().take(size_limit + 1), &mut destination)
+        .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError));
+    let fits = match written {
+        Ok(written) if written <= size_limit => record_drop_upload(&share, written as i64, &conn),
+        Ok(_) => Ok(false),
+        Err(e) => Err(ApiError::from(e)),
+    };
+    match fits {
+        Ok(true) => (),
+        result => {
+            // Never keep partial or oversized files around
+            fs::remove_file(&upload_path)?;
+            result?;
+            Err(CustomError::new(
+                "This file is larger than the space left in this file drop".to_string(),
+                Status::PayloadTooLarge,
+            ))?;
+        }
+    }
+
+    db::file::delete_pending_upload(&associated_upload.id, &conn)?;
+    thumbnails::enqueue(&thumbnail_queue, &upload_path);
+
+    Ok(Json(Message {
+        message: "Upload successful".to_string(),
+    }))
+}
+
+/// Counts a file dropped by a visitor against the limit of the file drop and the quota of its
+/// owner, returning whether it fits in both.
+fn record_drop_upload(share: &Share, bytes: i64, conn: &DBConnection) -> Result<bool, ApiError> {
+    let owner = share.user_id.map(Namespace::User);
+    if let Some(owner) = owner {
+        if !access::record_usage(owner, bytes, conn)? {
+            return Ok(false);
+        }
+    }
+    if !db::file::record_drop_upload(&share.link, bytes, conn)? {
+        if let Some(owner) = owner {
+            access::record_usage(owner, -bytes, conn)?;
+        }
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Get a form which browsers can use to unlock a password-protected share
+#[get("/shared/<id>/unlock")]
+pub fn unlock_form(id: String) -> Html<String> {
+    Html(pages::unlock_form(&id))
+}
+
+/// Unlock a password-protected share
+///
+/// On success, a cookie granting access to the shared content for a limited
+/// time is set. Like `login`, this route takes the same amount of time to
+/// respond whether or not the share exists or has a password, and refuses
+/// attempts for a while after too many wrong passwords for the share or from
+/// the client.
+#[post("/shared/<id>/unlock", format = "json", data = "<unlock>")]
+pub fn unlock_shared(
+    id: String,
+    unlock: Json<ShareUnlock>,
+    client: ClientInfo,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Json<Message>, ApiError> {
+    unlock_share(&id, &unlock.password, &client, &conn, &mut cookies)?;
+
+    Ok(Json(Message {
+        message: "Share unlocked successfully".to_string(),
+    }))
+}
+
+#[post("/shared/<id>/unlock", format = "form", data = "<unlock>", rank = 2)]
+pub fn unlock_shared_form(
+    id: String,
+    unlock: Form<ShareUnlock>,
+    client: ClientInfo,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Redirect, ApiError> {
+    unlock_share(&id, &unlock.password, &client, &conn, &mut cookies)?;
+
+    Ok(Redirect::to(format!("/file/shared/{}", Uri::percent_encode(&id))))
+}
+
+fn shared_content(
+    id: &str,
+    format: ResponseFormat,
+    conn: &DBConnection,
+    cookies: &mut Cookies,
+) -> Result<SharedContent, ApiError> {
+    let (share, shared_path) = find_share(id, conn)?;
+    if !is_unlocked(&share, cookies) {
+        if format == ResponseFormat::Html {
+            return Ok(SharedContent::Redirect(Box::new(Redirect::to(format!(
+                "/file/shared/{}/unlock",
+                Uri::percent_encode(id)
+            )))));
+        }
+        Err(locked_share_error())?;
+    }
+
+    if share.is_drop() {
+        if format == ResponseFormat::Html {
+            return Ok(SharedContent::Page(Html(pages::file_drop(&share.link))));
+        }
+        Err(drop_share_error())?;
+    }
+
+    if shared_path.is_dir() && format != ResponseFormat::Any {
+        return browse(&share, &shared_path, Path::new(""), format);
+    }
+
+    let file = consume_download(&share, conn, || get_named_file(&shared_path))?;
+    Ok(SharedContent::File(file))
+}
+
+/// Records a request for the content of a share in its access log, and makes the response
+/// record the number of bytes it sent once it is over.
+///
+/// Requests for links which do not exist are not recorded, and failing to record a request
+/// never affects the response.
+fn record_access<T: ServedStatus>(
+    link: &str,
+    client: &ClientInfo,
+    response: Result<T, ApiError>,
+    conn: &DBConnection,
+    share_log_queue: &ShareLogQueue,
+) -> Result<Logged<T>, ApiError> {
+    if !matches!(db::file::get_share(link, conn), Ok(Some(_))) {
+        return response.map(|content| Logged::new(content, None, share_log_queue));
+    }
+
+    let status = match &response {
+        Ok(content) => content.served_status(),
+        Err(error) => error.status(),
+    };
+    let access = NewShareAccess {
+        link: link.to_string(),
+        accessed_at: utils::unix_timestamp(),
+        ip: client.ip.map(utils::log_ip),
+        user_agent: client
+            .user_agent
+            .as_ref()
+            .map(|agent| agent.chars().take(MAX_LOGGED_USER_AGENT_LENGTH).collect()),
+        // Set once the response is over, as clients can stop downloads early
+        bytes_served: 0,
+        status: status.code as i32,
+    };
+    let access_id = db::file::record_share_access(&access, conn).ok();
+
+    response.map(|content| Logged::new(content, access_id, share_log_queue))
+}
+
+/// Makes sure that a slug is safe to use in URLs and easy to read out loud.
+fn validate_slug(slug: &str) -> Result<(), ApiError> {
+    if slug.len() < MIN_SLUG_LENGTH || slug.len() > MAX_SLUG_LENGTH {
+        Err(CustomError::new(
+            format!(
+                "Slugs must be between {} and {} characters long",
+                MIN_SLUG_LENGTH, MAX_SLUG_LENGTH
+            ),
+            Status::BadRequest,
+        ))?;
+    }
+    if !slug
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        || slug.starts_with('-')
+        || slug.ends_with('-')
+    {
+        Err(CustomError::new(
+            "Slugs may only contain lowercase letters, digits and inner hyphens".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Finds a share created by the given user, whether or not it has expired.
+fn find_own_share(link: &str, user: &User, conn: &DBConnection) -> Result<Share, ApiError> {
+    let share = db::file::get_share(link, conn)?
+        .filter(|share| share.user_id == Some(user.id))
+        .ok_or_else(|| {
+            CustomError::new("This share does not exist".to_string(), Status::NotFound)
+        })?;
+
+    Ok(share)
+}
+
+/// Finds a share which has not expired, along with the location of its content.
+fn find_share(id: &str, conn: &DBConnection) -> Result<(Share, PathBuf), ApiError> {
+    let share = db::file::get_share(id, conn)?.ok_or_else(|| {
         CustomError::new(
             "This share ID does not exist".to_string(),
             Status::BadRequest,
         )
     })?;
+    if share.is_expired() {
+        Err(CustomError::new(
+            "This share link has expired".to_string(),
+            Status::Gone,
+        ))?;
+    }
+
+    let file = match share.file_id {
+        Some(file_id) => db::file::get_tracked_file(file_id, conn)?,
+        None => None,
+    };
+    let shared_path = file.map(|file| file.full_path()).ok_or_else(|| {
+        CustomError::new(
+            "The shared content can no longer be found".to_string(),
+            Status::Gone,
+        )
+    })?;
+
+    Ok((share, shared_path))
+}
+
+/// Finds a share which is either not protected by a password or which was unlocked recently.
+fn find_unlocked_share(
+    id: &str,
+    conn: &DBConnection,
+    cookies: &mut Cookies,
+) -> Result<(Share, PathBuf), ApiError> {
+    let (share, shared_path) = find_share(id, conn)?;
+    if !is_unlocked(&share, cookies) {
+        Err(locked_share_error())?;
+    }
+    if share.is_drop() {
+        Err(drop_share_error())?;
+    }
+
+    Ok((share, shared_path))
+}
+
+/// Finds a file drop share which is either not protected by a password or which was unlocked
+/// recently.
+fn find_unlocked_drop(
+    id: &str,
+    conn: &DBConnection,
+    cookies: &mut Cookies,
+) -> Result<(Share, PathBuf), ApiError> {
+    let (share, shared_path) = find_share(id, conn)?;
+    if !is_unlocked(&share, cookies) {
+        Err(locked_share_error())?;
+    }
+    if !share.is_drop() {
+        Err(CustomError::new(
+            "This share does not accept uploads".to_string(),
+            Status::Forbidden,
+        ))?;
+    }
+
+    Ok((share, shared_path))
+}
+
+fn is_unlocked(share: &Share, cookies: &mut Cookies) -> bool {
+    let stored_hash = match &share.password {
+        Some(stored_hash) => stored_hash,
+        None => return true,
+    };
+
+    // The cookie is bound to the password hash of the share, so that it does not unlock another
+    // share created later with the same link
+    cookies
+        .get_private(&unlock_cookie_name(&share.link))
+        .and_then(|cookie| {
+            let (unlocked_until, hash) = cookie.value().split_once(':')?;
+            let unlocked_until = unlocked_until.parse::<i64>().ok()?;
+            Some(unlocked_until > utils::unix_timestamp() && hash == tokens::hash_token(stored_hash))
+        })
+        .unwrap_or(false)
+}
+
+fn locked_share_error() -> CustomError {
+    CustomError::new(
+        "This share is protected by a password, please unlock it first".to_string(),
+        Status::Unauthorized,
+    )
+}
+
+fn drop_share_error() -> CustomError {
+    CustomError::new(
+        "This share only accepts uploads".to_string(),
+        Status::Forbidden,
+    )
+}
+
+fn browse(
+    share: &Share,
+    shared_path: &Path,
+    path: &Path,
+    format: ResponseFormat,
+) -> Result<SharedContent, ApiError> {
+    let full_path = utils::resolve_within(shared_path, path)?;
+    if !full_path.is_dir() {
+        Err(CustomError::new(
+            "Only directories can be browsed".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    let mut contents = list_directory(&full_path)?;
+    match format {
+        ResponseFormat::Html => {
+            // Pages show subdirectories first and then sort by name
+            contents.contents.sort_by(|a, b| {
+                let a_is_file = a.element_type == FileSystemElementType::File;
+                let b_is_file = b.element_type == FileSystemElementType::File;
+                a_is_file.cmp(&b_is_file).then_with(|| a.name.cmp(&b.name))
+            });
+            Ok(SharedContent::Page(Html(pages::shared_directory(
+                &share.link,
+                path,
+                &contents,
+            ))))
+        }
+        _ => Ok(SharedContent::Listing(Json(contents))),
+    }
+}
+
+/// Opens the content of a share, using up one of its downloads once it could be opened, so
+/// that failed downloads do not count against the limit.
+fn consume_download<T>(
+    share: &Share,
+    conn: &DBConnection,
+    open: impl FnOnce() -> Result<T, ApiError>,
+) -> Result<T, ApiError> {
+    let limit_error = || {
+        CustomError::new(
+            "This share link has reached its download limit".to_string(),
+            Status::Gone,
+        )
+    };
+    // Avoid opening (or zipping) the content when it cannot be downloaded anymore anyway
+    if share.remaining_downloads == Some(0) {
+        Err(limit_error())?;
+    }
+
+    let content = open()?;
+    if share.remaining_downloads.is_some() && !db::file::consume_share_download(&share.link, conn)?
+    {
+        Err(limit_error())?;
+    }
+
+    Ok(content)
+}
+
+fn unlock_share(
+    id: &str,
+    password: &str,
+    client: &ClientInfo,
+    conn: &DBConnection,
+    cookies: &mut Cookies,
+) -> Result<(), ApiError> {
+    let now = utils::unix_timestamp();
+    login_throttle::check_share(id, client.ip, now, conn)?;
+
+    let share = find_share(id, conn);
+    let password_hash = match &share {
+        Ok((
+            Share {
+                password: Some(password_hash),
+                ..
+            },
+            _,
+        )) => Some(passwords::PasswordHash::from(password_hash)?),
+        _ => None,
+    };
+    // Unknown shares are verified against a dummy hash, see `routes::user::login`
+    let verified = passwords::verify_login_password(password, password_hash.as_ref()).is_ok();
+    let (share, _) = share?;
+    let stored_hash = match &share.password {
+        Some(stored_hash) => stored_hash,
+        None => return Ok(()),
+    };
+    if !verified {
+        login_throttle::record_share_failure(id, client.ip, now, conn)?;
+        Err(CustomError::new(
+            "Incorrect password".to_string(),
+            Status::Unauthorized,
+        ))?;
+    }
+
+    let unlocked_until = now + SHARE_UNLOCK_DURATION;
+    let cookie = Cookie::build(
+        unlock_cookie_name(id),
+        format!("{}:{}", unlocked_until, tokens::hash_token(stored_hash)),
+    )
+    .path(format!("/file/shared/{}", Uri::percent_encode(id)))
+    .finish();
+    cookies.add_private(cookie);
+
+    Ok(())
+}
+
+fn unlock_cookie_name(link: &str) -> String {
+    format!("share_{}", link)
+}
+
+fn relative_path_str<'a>(path: &'a Path, root: &Path) -> Result<&'a str, ApiError> {
+    path.strip_prefix(root)
+        .map_err(|_| ApiError::InternalServerError)?
+        .to_str()
+        .ok_or(ApiError::InternalServerError)
+}
+
+fn list_directory(path: &Path) -> Result<DirContents, ApiError> {
+    let mut contents = vec![];
+    let entries =
+        fs::read_dir(path).map_err(|e| CustomError::new(e.to_string(), Status::BadRequest))?;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        let (fs_element_type, fs_element_size) = {
+            if metadata.is_dir() {
+                (FileSystemElementType::Directory, 0)
+            } else {
+                (FileSystemElementType::File, metadata.len())
+            }
+        };
+        let fs_element = FileSystemElement {
+            element_type: fs_element_type,
+            name: entry.file_name().to_string_lossy().to_string(),
+            bytes: fs_element_size,
+        };
+        contents.push(fs_element);
+    }
 
-    let path = Path::new(&share.path);
-    get_named_file(path)
+    Ok(DirContents { contents })
+}
+
+fn get_thumbnail(path: &Path, format: ThumbnailFormat) -> Result<Thumbnail, ApiError> {
+    let thumbnail_path = thumbnails::thumbnail_for(path, format)?;
+    let file = NamedFile::open(thumbnail_path)
+        .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+    Ok(Thumbnail(file))
 }
 
 fn get_named_file(path: &Path) -> Result<NamedFile, ApiError> {
@@ -207,3 +1181,23 @@ fn get_named_file(path: &Path) -> Result<NamedFile, ApiError> {
         Ok(file)
     }
 }
+
+/// The status of a response, as recorded in the access log of shares.
+trait ServedStatus {
+    fn served_status(&self) -> Status {
+        Status::Ok
+    }
+}
+
+impl ServedStatus for NamedFile {}
+
+impl ServedStatus for Preview {}
+
+impl ServedStatus for SharedContent {
+    fn served_status(&self) -> Status {
+        match self {
+            SharedContent::Redirect(_) => Status::SeeOther,
+            _ => Status::Ok,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::access;
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::models::common_models::Message;
+use crate::models::file::FileSystemElementType;
+use crate::models::grant::{
+    Grant, GrantCreate, GrantList, GrantResult, NewGrant, ReceivedGrant, ReceivedGrantList,
+};
+use crate::models::user::{User, UserResult};
+use crate::utils::{self, Namespace};
+use crate::DBConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use std::path::Path;
+
+/// Give another registered user access to a file or directory
+///
+/// With the `read` permission, the recipient can list and download what was
+/// shared. The `write` permission also lets them upload files and create
+/// directories in it. Giving access again to the same user changes their
+/// permission.
+///
+/// The response is the same whether or not a user is registered with the
+/// given email address, so that this route cannot be used to find out who
+/// has an account.
+#[post("/", data = "<grant>")]
+pub fn create_grant(
+    grant: Json<GrantCreate>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    let grant = grant.into_inner();
+    let user_prefix = utils::root_path(Namespace::User(user.id));
+    let full_path = access::own_path(grant.path, &user)?;
+    if !full_path.exists() {
+        Err(CustomError::new(
+            "This path does not exist".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+    let email = utils::normalize_email(&grant.email);
+    if email == user.email {
+        Err(CustomError::new(
+            "You already have access to your own files".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    if let Some(grantee) = db::user::get_by_email(&email, &conn)? {
+        let path = full_path
+            .strip_prefix(&user_prefix)
+            .map_err(|_| ApiError::InternalServerError)?
+            .to_str()
+            .ok_or(ApiError::InternalServerError)?
+            .to_string();
+        db::grant::create(
+            &NewGrant {
+                owner_id: user.id,
+                grantee_id: grantee.id,
+                path,
+                permission: grant.permission.as_str().to_string(),
+                created_at: utils::unix_timestamp(),
+            },
+            &conn,
+        )?;
+    }
+
+    Ok(Json(Message {
+        message: "If a user is registered with this email address, they now have access"
+            .to_string(),
+    }))
+}
+
+/// List the grants given by the current user, most recent first
+#[get("/")]
+pub fn list_grants(user: User, conn: DBConnection) -> Result<Json<GrantList>, ApiError> {
+    let grants = db::grant::get_given_grants(user.id, &conn)?
+        .into_iter()
+        .map(|grant| {
+            let grantee = find_user(grant.grantee_id, &conn)?;
+            Ok(GrantResult {
+                id: grant.id,
+                path: grant.path,
+                grantee: UserResult::from(&grantee),
+                permission: grant.permission,
+                created_at: grant.created_at,
+            })
+        })
+        .collect::<Result<Vec<GrantResult>, ApiError>>()?;
+
+    Ok(Json(GrantList { grants }))
+}
+
+/// Revoke a grant, after which the recipient immediately loses access
+#[delete("/<id>")]
+pub fn revoke_grant(id: i32, user: User, conn: DBConnection) -> Result<Json<Message>, ApiError> {
+    if !db::grant::delete_given_grant(id, user.id, &conn)? {
+        Err(CustomError::new(
+            "This grant does not exist".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+
+    Ok(Json(Message {
+        message: "Grant revoked successfully".to_string(),
+    }))
+}
+
+/// List the files and directories other users shared with the current user
+///
+/// The `id` of each entry can be sent as `grant` along with a path relative to
+/// the shared item to the `/file` routes.
+#[get("/shared-with-me")]
+pub fn shared_with_me(user: User, conn: DBConnection) -> Result<Json<ReceivedGrantList>, ApiError> {
+    let grants = db::grant::get_received_grants(user.id, &conn)?
+        .into_iter()
+        .map(|grant| received_grant(grant, &conn))
+        .collect::<Result<Vec<ReceivedGrant>, ApiError>>()?;
+
+    Ok(Json(ReceivedGrantList { grants }))
+}
+
+fn received_grant(grant: Grant, conn: &DBConnection) -> Result<ReceivedGrant, ApiError> {
+    let owner = find_user(grant.owner_id, conn)?;
+    let shared_path = utils::root_path(Namespace::User(grant.owner_id)).join(&grant.path);
+    let element_type = if shared_path.is_dir() {
+        FileSystemElementType::Directory
+    } else {
+        FileSystemElementType::File
+    };
+    // A grant to the whole storage directory is named after its owner
+    let name = match Path::new(&grant.path).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => owner.display_name.to_string(),
+    };
+
+    Ok(ReceivedGrant {
+        id: grant.id,
+        name,
+        element_type,
+        owner: UserResult::from(&owner),
+        permission: grant.permission,
+        created_at: grant.created_at,
+    })
+}
+
+fn find_user(id: i32, conn: &DBConnection) -> Result<User, ApiError> {
+    db::user::get_by_id(id, conn)?.ok_or(ApiError::InternalServerError)
+}
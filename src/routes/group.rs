@@ -0,0 +1,202 @@
+use crate::access;
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::models::common_models::Message;
+use crate::models::group::{
+    Group, GroupCreate, GroupDetails, GroupList, GroupMember, GroupResult, GroupRole, MemberAdd,
+    MemberResult, NewGroup,
+};
+use crate::models::user::{User, UserResult};
+use crate::utils::{self, Namespace};
+use crate::DBConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use std::fs;
+
+/// Create a group, owned by the current user
+///
+/// The files of a group are stored in their own directory, which members can
+/// access by sending the group's ID as `group` alongside paths to the `/file`
+/// routes. An optional `quota` limits the number of bytes these files can take.
+#[post("/", data = "<group>")]
+pub fn create_group(
+    group: Json<GroupCreate>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<GroupResult>, ApiError> {
+    let group = group.into_inner();
+    let new_group = NewGroup {
+        name: validate_name(group.name)?,
+        quota: group.quota.map(|quota| quota.min(i64::MAX as u64) as i64),
+        created_at: utils::unix_timestamp(),
+    };
+    let created = db::group::create(&new_group, user.id, &conn)?;
+    fs::create_dir_all(utils::root_path(Namespace::Group(created.id)))?;
+
+    Ok(Json(group_result(&created, GroupRole::Owner)))
+}
+
+/// List the groups the current user is a member of
+#[get("/")]
+pub fn list_groups(user: User, conn: DBConnection) -> Result<Json<GroupList>, ApiError> {
+    let groups = db::group::get_user_groups(user.id, &conn)?
+        .iter()
+        .map(|(group, membership)| group_result(group, membership.role()))
+        .collect();
+
+    Ok(Json(GroupList { groups }))
+}
+
+#[get("/<id>")]
+pub fn inspect_group(
+    id: i32,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<GroupDetails>, ApiError> {
+    let (group, membership) = access::find_group(id, &user, &conn)?;
+    let members = db::group::get_members(id, &conn)?
+        .iter()
+        .map(|(member, member_user)| MemberResult {
+            user: UserResult::from(member_user),
+            role: member.role().as_str().to_string(),
+        })
+        .collect();
+
+    Ok(Json(GroupDetails {
+        group: group_result(&group, membership.role()),
+        members,
+    }))
+}
+
+/// Rename a group and change its quota, which only owners can do
+#[put("/<id>", data = "<group>")]
+pub fn update_group(
+    id: i32,
+    group: Json<GroupCreate>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<GroupResult>, ApiError> {
+    access::find_owned_group(id, &user, &conn)?;
+    let group = group.into_inner();
+    let quota = group.quota.map(|quota| quota.min(i64::MAX as u64) as i64);
+    db::group::update_group(id, &validate_name(group.name)?, quota, &conn)?;
+
+    let (group, membership) = access::find_group(id, &user, &conn)?;
+    Ok(Json(group_result(&group, membership.role())))
+}
+
+/// Delete a group along with all of its files, which only owners can do
+#[delete("/<id>")]
+pub fn delete_group(id: i32, user: User, conn: DBConnection) -> Result<Json<Message>, ApiError> {
+    access::find_owned_group(id, &user, &conn)?;
+    // Files are deleted first, so that a group created later with the same ID never gets them
+    let root = utils::root_path(Namespace::Group(id));
+    if root.exists() {
+        fs::remove_dir_all(root)?;
+    }
+    db::group::delete_group(id, &conn)?;
+
+    Ok(Json(Message {
+        message: "Group deleted successfully".to_string(),
+    }))
+}
+
+/// Add a registered user to a group, or change the role of a member
+///
+/// Like `create_grant`, the response is the same whether or not a user is
+/// registered with the given email address.
+#[post("/<id>/members", data = "<member>")]
+pub fn add_member(
+    id: i32,
+    member: Json<MemberAdd>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    access::find_owned_group(id, &user, &conn)?;
+    let email = utils::normalize_email(&member.email);
+    if let Some(new_member) = db::user::get_by_email(&email, &conn)? {
+        if member.role != GroupRole::Owner {
+            ensure_other_owner(id, new_member.id, &conn)?;
+        }
+
+        db::group::set_member(
+            &GroupMember {
+                group_id: id,
+                user_id: new_member.id,
+                role: member.role.as_str().to_string(),
+            },
+            &conn,
+        )?;
+    }
+
+    Ok(Json(Message {
+        message: "If a user is registered with this email address, they are now a member"
+            .to_string(),
+    }))
+}
+
+/// Remove a member from a group
+///
+/// Owners can remove anyone, and any member can leave a group by removing
+/// themselves, as long as the group keeps at least one owner.
+#[delete("/<id>/members/<user_id>")]
+pub fn remove_member(
+    id: i32,
+    user_id: i32,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    if user_id != user.id {
+        access::find_owned_group(id, &user, &conn)?;
+    }
+    ensure_other_owner(id, user_id, &conn)?;
+    if !db::group::remove_member(id, user_id, &conn)? {
+        Err(CustomError::new(
+            "This user is not a member of this group".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+
+    Ok(Json(Message {
+        message: "Member removed successfully".to_string(),
+    }))
+}
+
+fn validate_name(name: String) -> Result<String, ApiError> {
+    let name = name.trim();
+    if name.is_empty() {
+        Err(CustomError::new(
+            "Group names must not be empty".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    Ok(name.to_string())
+}
+
+/// Makes sure that a group would still have an owner if the given user stopped being one.
+fn ensure_other_owner(group_id: i32, user_id: i32, conn: &DBConnection) -> Result<(), ApiError> {
+    let is_owner = match db::group::get_membership(group_id, user_id, conn)? {
+        Some(membership) => membership.role() == GroupRole::Owner,
+        None => false,
+    };
+    if is_owner && db::group::count_owners(group_id, conn)? <= 1 {
+        Err(CustomError::new(
+            "Groups must keep at least one owner".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    Ok(())
+}
+
+fn group_result(group: &Group, role: GroupRole) -> GroupResult {
+    GroupResult {
+        id: group.id,
+        name: group.name.to_string(),
+        role: role.as_str().to_string(),
+        quota: group.quota,
+        used_bytes: group.used_bytes.max(0) as u64,
+        created_at: group.created_at,
+    }
+}
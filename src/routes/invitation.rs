@@ -0,0 +1,117 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::guards::Admin;
+use crate::mailer::{self, Email};
+use crate::models::common_models::Message;
+use crate::models::invitation::{
+    CreatedInvitation, InvitationCreate, InvitationList, InvitationResult, NewInvitation,
+};
+use crate::tokens;
+use crate::utils::{self, unix_timestamp};
+use crate::{DBConnection, MailQueue};
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+
+/// Create an invitation code, which lets people register while registrations are not open to
+/// everyone
+///
+/// Invitations can be used once unless `max_uses` says otherwise. When an `email` is given,
+/// only an account with this address can be created with the invitation, and its code is
+/// emailed there. The code is only returned by this route.
+#[post("/", data = "<invitation>")]
+pub fn create_invitation(
+    invitation: Json<InvitationCreate>,
+    admin: Admin,
+    conn: DBConnection,
+    mail_queue: State<MailQueue>,
+) -> Result<Json<CreatedInvitation>, ApiError> {
+    let invitation = invitation.into_inner();
+    let email = invitation
+        .email
+        .as_deref()
+        .map(utils::normalize_email)
+        .filter(|email| !email.is_empty());
+    if email.as_ref().map_or(false, |email| !email.contains('@')) {
+        Err(CustomError::new(
+            "This email address is not valid".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+    let max_uses = invitation.max_uses.unwrap_or(1);
+    if max_uses == 0 {
+        Err(CustomError::new(
+            "Invitations must allow at least one use".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    let code = tokens::random_string()?;
+    let now = unix_timestamp();
+    let created = db::invitation::create(
+        &NewInvitation {
+            code_hash: tokens::hash_token(&code),
+            created_by: admin.0.id,
+            email: email.clone(),
+            max_uses: max_uses.min(i32::MAX as u32) as i32,
+            created_at: now,
+            expires_at: invitation
+                .expires_in
+                .map(|expires_in| utils::timestamp_after(now, expires_in)),
+        },
+        &conn,
+    )?;
+    if let Some(email) = email {
+        mailer::enqueue(
+            &mail_queue,
+            Email {
+                to: email,
+                subject: "You are invited to create an account".to_string(),
+                body: format!(
+                    "Hello,\n\n\
+                     {} invited you to create an account. Please use this invitation code \
+                     to register with this email address:\n\n{}\n",
+                    admin.0.display_name, code
+                ),
+            },
+        );
+    }
+
+    Ok(Json(CreatedInvitation {
+        code,
+        details: InvitationResult::from(&created),
+    }))
+}
+
+/// List every invitation, without their codes
+#[get("/")]
+pub fn list_invitations(
+    _admin: Admin,
+    conn: DBConnection,
+) -> Result<Json<InvitationList>, ApiError> {
+    let invitations = db::invitation::get_all(&conn)?
+        .iter()
+        .map(InvitationResult::from)
+        .collect();
+
+    Ok(Json(InvitationList { invitations }))
+}
+
+/// Revoke an invitation, whose code cannot be used anymore
+#[delete("/<id>")]
+pub fn revoke_invitation(
+    id: i32,
+    _admin: Admin,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    if !db::invitation::delete_invitation(id, &conn)? {
+        Err(CustomError::new(
+            "This invitation does not exist".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+
+    Ok(Json(Message {
+        message: "Invitation revoked successfully".to_string(),
+    }))
+}
@@ -0,0 +1,162 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::guards::ClientInfo;
+use crate::login_throttle;
+use crate::models::common_models::Message;
+use crate::models::totp::{
+    NewRecoveryCode, RecoveryCodes, TotpCode, TotpDisable, TotpSecret, TotpSetup, TotpStatus,
+};
+use crate::models::user::User;
+use crate::passwords;
+use crate::qr::{self, QrImage};
+use crate::totp;
+use crate::utils;
+use crate::DBConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+/// Get whether two-factor authentication is enabled for the current user
+#[get("/")]
+pub fn totp_status(user: User, conn: DBConnection) -> Result<Json<TotpStatus>, ApiError> {
+    let enabled = match db::totp::get_secret(user.id, &conn)? {
+        Some(secret) => secret.enabled,
+        None => false,
+    };
+    let remaining_recovery_codes = if enabled {
+        db::totp::count_recovery_codes(user.id, &conn)?
+    } else {
+        0
+    };
+
+    Ok(Json(TotpStatus {
+        enabled,
+        remaining_recovery_codes,
+    }))
+}
+
+/// Start enrolling in two-factor authentication
+///
+/// This returns a new secret to add to an authenticator application, which is only required
+/// to log in once a code generated from it is sent to `confirm_totp`.
+#[post("/")]
+pub fn setup_totp(user: User, conn: DBConnection) -> Result<Json<TotpSetup>, ApiError> {
+    ensure_disabled(user.id, &conn)?;
+
+    let secret = totp::generate_secret()?;
+    db::totp::save_secret(
+        &TotpSecret {
+            user_id: user.id,
+            secret: secret.to_string(),
+            enabled: false,
+            last_used_step: None,
+            created_at: utils::unix_timestamp(),
+        },
+        &conn,
+    )?;
+
+    Ok(Json(TotpSetup {
+        uri: totp::provisioning_uri(&secret, &user.email),
+        secret,
+    }))
+}
+
+/// Get the provisioning URI of an enrollment as a QR code, in the `svg` (default) or `png` format
+#[get("/qr?<format>")]
+pub fn totp_qr_code(
+    format: Option<String>,
+    user: User,
+    conn: DBConnection,
+) -> Result<QrImage, ApiError> {
+    let secret = pending_secret(user.id, &conn)?;
+
+    qr::render(
+        &totp::provisioning_uri(&secret.secret, &user.email),
+        format.as_deref().unwrap_or("svg"),
+    )
+}
+
+/// Finish enrolling in two-factor authentication with a code from the authenticator application
+///
+/// The recovery codes returned can each be used once instead of a code, and are never shown again.
+#[post("/confirm", data = "<code>")]
+pub fn confirm_totp(
+    code: Json<TotpCode>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    let secret = pending_secret(user.id, &conn)?;
+    let step = totp::verify_code(&secret.secret, code.code.trim(), None).ok_or_else(|| {
+        CustomError::new(
+            "Invalid two-factor authentication code".to_string(),
+            Status::BadRequest,
+        )
+    })?;
+
+    let (recovery_codes, hashes): (Vec<String>, Vec<String>) =
+        totp::generate_recovery_codes()?.into_iter().unzip();
+    let new_codes: Vec<NewRecoveryCode> = hashes
+        .into_iter()
+        .map(|code_hash| NewRecoveryCode {
+            user_id: user.id,
+            code_hash,
+        })
+        .collect();
+    db::totp::enable(user.id, step, &new_codes, &conn)?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+/// Disable two-factor authentication, which requires the password and a code of the user
+#[delete("/", data = "<credentials>")]
+pub fn disable_totp(
+    credentials: Json<TotpDisable>,
+    user: User,
+    client: ClientInfo,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    passwords::verify_current_password(&user, &credentials.password, client.ip, &conn)?;
+    match db::totp::get_secret(user.id, &conn)? {
+        Some(secret) if secret.enabled => (),
+        _ => Err(CustomError::new(
+            "Two-factor authentication is not enabled".to_string(),
+            Status::BadRequest,
+        ))?,
+    }
+    if let Err(error) = totp::verify_second_factor(user.id, Some(&credentials.code), &conn) {
+        // Wrong codes count as failed login attempts, like they do in `routes::user::login`
+        login_throttle::record_failure(&user.email, client.ip, utils::unix_timestamp(), &conn)?;
+        return Err(error);
+    }
+
+    db::totp::disable(user.id, &conn)?;
+
+    Ok(Json(Message {
+        message: "Two-factor authentication disabled successfully".to_string(),
+    }))
+}
+
+/// Makes sure that a user did not already enable two-factor authentication.
+fn ensure_disabled(user_id: i32, conn: &DBConnection) -> Result<(), ApiError> {
+    if let Some(secret) = db::totp::get_secret(user_id, conn)? {
+        if secret.enabled {
+            Err(CustomError::new(
+                "Two-factor authentication is already enabled".to_string(),
+                Status::Conflict,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the secret of an enrollment which was started but not confirmed yet.
+fn pending_secret(user_id: i32, conn: &DBConnection) -> Result<TotpSecret, ApiError> {
+    ensure_disabled(user_id, conn)?;
+
+    db::totp::get_secret(user_id, conn)?.ok_or_else(|| {
+        ApiError::from(CustomError::new(
+            "Two-factor authentication enrollment was not started".to_string(),
+            Status::NotFound,
+        ))
+    })
+}
@@ -1,47 +1,95 @@
 use crate::api_error::{ApiError, CustomError};
 use crate::db;
-use crate::models::user::{ActiveSession, UserCreate, UserLogin, UserResult};
+use crate::email_verification;
+use crate::guards::{ClientInfo, SessionId};
+use crate::login_throttle;
+use crate::models::common_models::Message;
+use crate::models::token::{CreatedToken, NewApiToken, TokenCreate, TokenList, TokenResult};
+use crate::models::user::{
+    ActiveSession, EmailChange, EmailVerificationConfirm, PasswordChange, PasswordReset,
+    PasswordResetRequest, ProfileUpdate, Registration, SessionList, SessionResult, User, UserLogin,
+    UserResult,
+};
+use crate::password_policy;
+use crate::password_resets;
 use crate::passwords;
-use crate::{DBConnection, SessionStore};
+use crate::tokens;
+use crate::totp;
+use crate::utils::{self, unix_timestamp};
+use crate::{DBConnection, MailQueue, PasswordResetQueue};
+use diesel::Connection;
 use rocket::http::{Cookie, Cookies, Status};
 use rocket::State;
 use rocket_contrib::json::Json;
 use std::env;
-use std::time::Instant;
+use time::Duration;
 use uuid::Uuid;
 
-#[post("/register", data = "<user>")]
+/// Create an account and log into it
+///
+/// Anyone can register when `ALLOW_REGISTRATIONS` is set. Otherwise an `invitation` code
+/// created by an administrator is required, and only the email address it is reserved to can
+/// be used, if any. When `REQUIRE_EMAIL_VERIFICATION` is set, a verification token is emailed
+/// to the user, who cannot upload files before sending it to `verify_email`.
+///
+/// Passwords which are too short, appeared in data breaches or are too easy to guess are
+/// refused with the reasons listed in `errors`.
+#[post("/register", data = "<registration>")]
 pub fn register(
     conn: DBConnection,
-    user: Json<UserCreate>,
-    active_session_ids: State<SessionStore>,
+    registration: Json<Registration>,
+    client: ClientInfo,
+    mail_queue: State<MailQueue>,
     mut cookies: Cookies,
 ) -> Result<Json<UserResult>, ApiError> {
-    env::var("ALLOW_REGISTRATIONS").map_err(|_| {
-        CustomError::new(
-            "User registrations have been disabled".to_string(),
-            Status::Unauthorized,
-        )
-    })?;
+    let Registration {
+        mut user,
+        invitation,
+    } = registration.into_inner();
+    user.email = utils::normalize_email(&user.email);
+    let invitation = if env::var("ALLOW_REGISTRATIONS").is_ok() {
+        None
+    } else {
+        let code = invitation.ok_or_else(|| {
+            CustomError::new(
+                "User registrations have been disabled".to_string(),
+                Status::Unauthorized,
+            )
+        })?;
+        let invitation = db::invitation::get_by_hash(&tokens::hash_token(code.trim()), &conn)?
+            .filter(|invitation| invitation.allows(&user.email, unix_timestamp()))
+            .ok_or_else(invalid_invitation)?;
+        Some(invitation)
+    };
+    password_policy::validate(
+        "password",
+        &user.password,
+        &[&user.email, &user.display_name],
+    )?;
 
-    let mut user = user.into_inner();
     let password_hash = passwords::hash_password(&user.password)?.to_string();
     user.password = password_hash;
 
-    db::user::create(&user, &*conn)?;
-    let created_user = db::user::get_by_email(&user.email, &*conn)?
-        .ok_or_else(|| ApiError::InternalServerError)?;
+    conn.transaction::<_, ApiError, _>(|| {
+        if let Some(invitation) = &invitation {
+            if !db::invitation::use_invitation(invitation.id, &conn)? {
+                Err(invalid_invitation())?;
+            }
+        }
+        db::user::create(&user, &conn)
+    })?;
+    let mut created_user =
+        db::user::get_by_email(&user.email, &*conn)?.ok_or(ApiError::InternalServerError)?;
 
-    let session_id = Uuid::new_v4();
-    cookies.add_private(Cookie::new("session", session_id.to_string()));
-    let mut session_ids = active_session_ids.write();
-    session_ids.insert(
-        session_id,
-        ActiveSession {
-            email: user.email,
-            created: Instant::now(),
-        },
-    );
+    if invitation.map_or(false, |invitation| invitation.email.is_some()) {
+        // The invitation code was sent to this address by an administrator
+        db::user::set_email_verified(created_user.id, &conn)?;
+        created_user.email_verified = true;
+    } else if utils::email_verification_required() {
+        email_verification::send_verification_email(&created_user, &mail_queue, &conn)?;
+    }
+
+    start_session(created_user.id, false, client, &conn, &mut cookies)?;
 
     Ok(Json(UserResult::from(&created_user)))
 }
@@ -54,43 +102,456 @@ pub fn register(
 /// finding out that a user does not exist in the database is very fast, so we have
 /// to simulate a delay to ensure that no information can be gathered from the timing
 /// of the responses from this route.
+///
+/// Users who enabled two-factor authentication must also send a `code`, which is only
+/// checked once the password is known to be correct. Once logged in, the stored hash of the
+/// password is replaced if it was made with an outdated algorithm or parameters.
+///
+/// Failed attempts are counted against the email address and the client address. Once there
+/// were too many of them, logins are refused for a while before any password is verified,
+/// see `login_throttle`. Users whose account was disabled by an administrator are only told so
+/// once they sent the correct password.
 #[post("/login", data = "<user>")]
 pub fn login(
     conn: DBConnection,
     user: Json<UserLogin>,
-    active_session_ids: State<SessionStore>,
+    client: ClientInfo,
     mut cookies: Cookies,
 ) -> Result<Json<UserResult>, ApiError> {
-    let user = user.into_inner();
-    let db_user = db::user::get_by_email(&user.email, &*conn)?.ok_or_else(|| {
-        match passwords::hash_password(&"Dummy Password") {
-            Ok(_) => (),
-            Err(_) => return ApiError::InternalServerError,
+    let mut user = user.into_inner();
+    user.email = utils::normalize_email(&user.email);
+    let now = unix_timestamp();
+    login_throttle::check(&user.email, client.ip, now, &conn)?;
+
+    let db_user = match db::user::get_by_email(&user.email, &*conn)? {
+        Some(db_user) => {
+            let password_hash = passwords::PasswordHash::from(&db_user.password)?;
+            Some((db_user, password_hash))
+        }
+        None => None,
+    };
+    let password_hash = db_user.as_ref().map(|(_, password_hash)| password_hash);
+    let verified = passwords::verify_login_password(&user.password, password_hash).is_ok();
+    let (db_user, password_hash) = match db_user.filter(|_| verified) {
+        Some(db_user) => db_user,
+        None => {
+            login_throttle::record_failure(&user.email, client.ip, now, &conn)?;
+            return Err(ApiError::from(CustomError::new(
+                "User not found or incorrect password".to_string(),
+                Status::BadRequest,
+            )));
+        }
+    };
+    if db_user.disabled {
+        Err(CustomError::new(
+            "This account has been disabled by an administrator".to_string(),
+            Status::Forbidden,
+        ))?;
+    }
+    if let Err(error) = totp::verify_second_factor(db_user.id, user.code.as_deref(), &conn) {
+        // Asking for the code is not a failed attempt, a wrong code is
+        if user.code.is_some() {
+            login_throttle::record_failure(&user.email, client.ip, now, &conn)?;
         }
-        ApiError::from(CustomError::new(
-            "User not found or incorrect password".to_string(),
+        return Err(error);
+    }
+    login_throttle::record_success(&user.email, &conn)?;
+    if password_hash.needs_rehash() {
+        // The password is known now, so the hash can be upgraded to the current algorithm
+        let password_hash = passwords::hash_password(&user.password)?.to_string();
+        db::user::update_password(db_user.id, &password_hash, &conn)?;
+    }
+
+    start_session(db_user.id, user.remember, client, &conn, &mut cookies)?;
+
+    Ok(Json(UserResult::from(&db_user)))
+}
+
+/// Get the current user
+#[get("/me")]
+pub fn me(user: User) -> Json<UserResult> {
+    Json(UserResult::from(&user))
+}
+
+/// Change the display name of the current user
+#[put("/me", data = "<profile>")]
+pub fn update_profile(
+    profile: Json<ProfileUpdate>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<UserResult>, ApiError> {
+    let display_name = profile.display_name.trim();
+    if display_name.is_empty() {
+        Err(CustomError::new(
+            "Display names must not be empty".to_string(),
             Status::BadRequest,
-        ))
-    })?;
+        ))?;
+    }
+    db::user::update_display_name(user.id, display_name, &conn)?;
+
+    Ok(Json(UserResult {
+        display_name: display_name.to_string(),
+        ..UserResult::from(&user)
+    }))
+}
+
+/// Change the password of the current user, which logs out all their other sessions and
+/// revokes their API tokens
+#[put("/me/password", data = "<change>")]
+pub fn change_password(
+    change: Json<PasswordChange>,
+    user: User,
+    session_id: SessionId,
+    client: ClientInfo,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    passwords::verify_current_password(&user, &change.current_password, client.ip, &conn)?;
+    password_policy::validate(
+        "new_password",
+        &change.new_password,
+        &[&user.email, &user.display_name],
+    )?;
+
+    let password_hash = passwords::hash_password(&change.new_password)?.to_string();
+    db::user::update_password(user.id, &password_hash, &conn)?;
+    db::session::delete_other_sessions(user.id, &session_id.key(), &conn)?;
+    db::token::delete_user_tokens(user.id, &conn)?;
+
+    Ok(Json(Message {
+        message: "Password changed successfully".to_string(),
+    }))
+}
+
+/// Change the email address the current user logs in with
+///
+/// The new address has to be verified, so a verification token is emailed to it.
+#[put("/me/email", data = "<change>")]
+pub fn change_email(
+    change: Json<EmailChange>,
+    user: User,
+    client: ClientInfo,
+    conn: DBConnection,
+    mail_queue: State<MailQueue>,
+) -> Result<Json<UserResult>, ApiError> {
+    passwords::verify_current_password(&user, &change.password, client.ip, &conn)?;
+
+    let email = utils::normalize_email(&change.email);
+    if email.is_empty() || !email.contains('@') {
+        Err(CustomError::new(
+            "This email address is not valid".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+    if email == user.email {
+        return Ok(Json(UserResult::from(&user)));
+    }
+    db::user::update_email(user.id, &email, &conn)?;
+
+    let user = User {
+        email,
+        email_verified: false,
+        ..user
+    };
+    email_verification::send_verification_email(&user, &mail_queue, &conn)?;
+
+    Ok(Json(UserResult::from(&user)))
+}
+
+/// Send a new token to verify the email address of the current user
+#[post("/email/verification")]
+pub fn request_email_verification(
+    user: User,
+    conn: DBConnection,
+    mail_queue: State<MailQueue>,
+) -> Result<Json<Message>, ApiError> {
+    if user.email_verified {
+        Err(CustomError::new(
+            "This email address is already verified".to_string(),
+            Status::Conflict,
+        ))?;
+    }
+    email_verification::send_verification_email(&user, &mail_queue, &conn)?;
+
+    Ok(Json(Message {
+        message: "A verification token was sent to your email address".to_string(),
+    }))
+}
+
+/// Verify an email address with a token sent by `register`, `change_email` or
+/// `request_email_verification`
+///
+/// Tokens stop working once the user changes their email address again.
+#[post("/email/verification/confirm", data = "<verification>")]
+pub fn verify_email(
+    verification: Json<EmailVerificationConfirm>,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    let token_hash = tokens::hash_token(verification.token.trim());
+    let verification = db::user::take_email_verification(&token_hash, &conn)?
+        .filter(|verification| verification.expires_at > unix_timestamp())
+        .ok_or_else(invalid_verification)?;
+    let user = db::user::get_by_id(verification.user_id, &conn)?
+        .filter(|user| user.email == verification.email)
+        .ok_or_else(invalid_verification)?;
+    db::user::set_email_verified(user.id, &conn)?;
+
+    Ok(Json(Message {
+        message: "Email address verified successfully".to_string(),
+    }))
+}
+
+/// Send a token to reset the password of the account with the given email address
+///
+/// The response does not reveal whether the email address is registered, and the token is
+/// created and sent in the background so that the time the response takes does not reveal it
+/// either.
+#[post("/password/reset", data = "<request>")]
+pub fn request_password_reset(
+    request: Json<PasswordResetRequest>,
+    reset_queue: State<PasswordResetQueue>,
+) -> Json<Message> {
+    password_resets::enqueue(&reset_queue, utils::normalize_email(&request.email));
 
-    let password_hash = passwords::PasswordHash::from(&db_user.password)?;
-    passwords::verify_password(&user.password, &password_hash).map_err(|_| {
+    Json(Message {
+        message: "If this email address is registered, a reset token was sent to it".to_string(),
+    })
+}
+
+/// Choose a new password with a token sent by `request_password_reset`
+///
+/// Tokens can only be used once, every session of the user is logged out and their API tokens
+/// are revoked.
+#[post("/password/reset/confirm", data = "<reset>")]
+pub fn reset_password(
+    reset: Json<PasswordReset>,
+    conn: DBConnection,
+) -> Result<Json<Message>, ApiError> {
+    let token_hash = tokens::hash_token(reset.token.trim());
+    let invalid_token = || {
         CustomError::new(
-            "User not found or incorrect password".to_string(),
+            "This reset token is invalid or expired".to_string(),
             Status::BadRequest,
         )
-    })?;
+    };
+    let reset_token = db::user::get_password_reset(&token_hash, &conn)?
+        .filter(|reset_token| reset_token.expires_at > unix_timestamp())
+        .ok_or_else(invalid_token)?;
+    // The token is only used once the new password is accepted, so that it can be corrected
+    let user = db::user::get_by_id(reset_token.user_id, &conn)?.ok_or_else(invalid_token)?;
+    password_policy::validate(
+        "new_password",
+        &reset.new_password,
+        &[&user.email, &user.display_name],
+    )?;
+    db::user::take_password_reset(&token_hash, &conn)?.ok_or_else(invalid_token)?;
+
+    let password_hash = passwords::hash_password(&reset.new_password)?.to_string();
+    db::user::update_password(user.id, &password_hash, &conn)?;
+    db::session::delete_user_sessions(user.id, &conn)?;
+    db::token::delete_user_tokens(user.id, &conn)?;
+
+    Ok(Json(Message {
+        message: "Password reset successfully".to_string(),
+    }))
+}
+
+/// Ends the session the request was sent with and clears its cookie
+#[post("/logout")]
+pub fn logout(
+    session_id: SessionId,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Json<Message>, ApiError> {
+    db::session::delete_session(&session_id.key(), &conn)?;
+    cookies.remove_private(Cookie::named("session"));
+
+    Ok(Json(Message {
+        message: "Logged out successfully".to_string(),
+    }))
+}
+
+/// List the active sessions of the current user, including the one used for this request
+#[get("/sessions")]
+pub fn list_sessions(
+    user: User,
+    session_id: SessionId,
+    conn: DBConnection,
+) -> Result<Json<SessionList>, ApiError> {
+    let key = session_id.key();
+    let now = unix_timestamp();
+    let sessions = db::session::get_user_sessions(user.id, &conn)?
+        .iter()
+        .filter(|session| !session.is_expired(now))
+        .map(|session| SessionResult::from(session, session.id == key))
+        .collect();
+
+    Ok(Json(SessionList { sessions }))
+}
 
+/// Revoke one of the sessions of the current user, which is logged out immediately
+#[delete("/sessions/<id>")]
+pub fn revoke_session(
+    id: String,
+    user: User,
+    session_id: SessionId,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Json<Message>, ApiError> {
+    let session = db::session::get_user_sessions(user.id, &conn)?
+        .into_iter()
+        .find(|session| session.public_id == id)
+        .ok_or_else(|| {
+            CustomError::new("This session does not exist".to_string(), Status::NotFound)
+        })?;
+    db::session::delete_session(&session.id, &conn)?;
+    if session.id == session_id.key() {
+        cookies.remove_private(Cookie::named("session"));
+    }
+
+    Ok(Json(Message {
+        message: "Session revoked successfully".to_string(),
+    }))
+}
+
+/// Revoke all the sessions of the current user, including the one used for this request
+#[delete("/sessions")]
+pub fn revoke_all_sessions(
+    user: User,
+    conn: DBConnection,
+    mut cookies: Cookies,
+) -> Result<Json<Message>, ApiError> {
+    db::session::delete_user_sessions(user.id, &conn)?;
+    cookies.remove_private(Cookie::named("session"));
+
+    Ok(Json(Message {
+        message: "All sessions revoked successfully".to_string(),
+    }))
+}
+
+/// Create a personal API token, to be sent in an `Authorization: Bearer` header
+///
+/// The token is only returned by this route, and can only be used with the routes its
+/// scopes allow: `read` to list, download and preview files, `upload` to upload files and
+/// create directories, and `shares` to manage shares.
+#[post("/tokens", data = "<token>")]
+pub fn create_token(
+    token: Json<TokenCreate>,
+    user: User,
+    conn: DBConnection,
+) -> Result<Json<CreatedToken>, ApiError> {
+    let token = token.into_inner();
+    let name = token.name.trim();
+    if name.is_empty() {
+        Err(CustomError::new(
+            "Token names must not be empty".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+    if token.scopes.is_empty() {
+        Err(CustomError::new(
+            "Tokens must have at least one scope".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    let mut scopes: Vec<&str> = token.scopes.iter().map(|scope| scope.as_str()).collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+    let now = unix_timestamp();
+    let (value, token_hash) = tokens::generate_token()?;
+    let created = db::token::create(
+        &NewApiToken {
+            user_id: user.id,
+            name: name.to_string(),
+            token_hash,
+            scopes: scopes.join(","),
+            created_at: now,
+            expires_at: token
+                .expires_in
+                .map(|expires_in| utils::timestamp_after(now, expires_in)),
+        },
+        &conn,
+    )?;
+
+    Ok(Json(CreatedToken {
+        token: value,
+        details: TokenResult::from(&created),
+    }))
+}
+
+/// List the API tokens of the current user, without their values
+#[get("/tokens")]
+pub fn list_tokens(user: User, conn: DBConnection) -> Result<Json<TokenList>, ApiError> {
+    let tokens = db::token::get_user_tokens(user.id, &conn)?
+        .iter()
+        .map(TokenResult::from)
+        .collect();
+
+    Ok(Json(TokenList { tokens }))
+}
+
+/// Revoke an API token, which stops working immediately
+#[delete("/tokens/<id>")]
+pub fn revoke_token(id: i32, user: User, conn: DBConnection) -> Result<Json<Message>, ApiError> {
+    if !db::token::delete_user_token(id, user.id, &conn)? {
+        Err(CustomError::new(
+            "This token does not exist".to_string(),
+            Status::NotFound,
+        ))?;
+    }
+
+    Ok(Json(Message {
+        message: "Token revoked successfully".to_string(),
+    }))
+}
+
+fn invalid_invitation() -> CustomError {
+    CustomError::new(
+        "This invitation code is invalid or expired".to_string(),
+        Status::Forbidden,
+    )
+}
+
+fn invalid_verification() -> CustomError {
+    CustomError::new(
+        "This verification token is invalid or expired".to_string(),
+        Status::BadRequest,
+    )
+}
+
+/// Creates a new session for the given user and sets the cookie that identifies it.
+///
+/// Sessions expire after `utils::session_lifetime` and, unless `remember` is set, after
+/// `utils::session_idle_timeout` without any request.
+fn start_session(
+    user_id: i32,
+    remember: bool,
+    client: ClientInfo,
+    conn: &DBConnection,
+    cookies: &mut Cookies,
+) -> Result<(), ApiError> {
     let session_id = Uuid::new_v4();
-    cookies.add_private(Cookie::new("session", session_id.to_string()));
-    let mut active_session_ids = active_session_ids.write();
-    active_session_ids.insert(
-        session_id,
-        ActiveSession {
-            email: db_user.email.to_string(),
-            created: Instant::now(),
+    let now = unix_timestamp();
+    let lifetime = utils::session_lifetime(remember);
+    db::session::create(
+        &ActiveSession {
+            id: tokens::hash_token(&session_id.to_string()),
+            public_id: Uuid::new_v4().to_string(),
+            user_id,
+            created_at: now,
+            last_seen: now,
+            user_agent: client.user_agent,
+            ip: client.ip.map(|ip| ip.to_string()),
+            expires_at: now + lifetime,
+            idle_timeout: utils::session_idle_timeout(remember),
         },
-    );
+        conn,
+    )?;
+    let cookie = Cookie::build("session", session_id.to_string())
+        .max_age(Duration::seconds(lifetime))
+        .expires(time::now() + Duration::seconds(lifetime))
+        .finish();
+    cookies.add_private(cookie);
 
-    Ok(Json(UserResult::from(&db_user)))
+    Ok(())
 }
@@ -1,7 +1,165 @@
+table! {
+    api_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Text,
+        created_at -> BigInt,
+        expires_at -> Nullable<BigInt>,
+        last_used_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    email_verifications (token_hash) {
+        token_hash -> Text,
+        user_id -> Integer,
+        email -> Text,
+        created_at -> BigInt,
+        expires_at -> BigInt,
+    }
+}
+
+table! {
+    files (id) {
+        id -> Integer,
+        owner_id -> Integer,
+        path -> Text,
+    }
+}
+
+table! {
+    grants (id) {
+        id -> Integer,
+        owner_id -> Integer,
+        grantee_id -> Integer,
+        path -> Text,
+        permission -> Text,
+        created_at -> BigInt,
+    }
+}
+
+table! {
+    group_members (group_id, user_id) {
+        group_id -> Integer,
+        user_id -> Integer,
+        role -> Text,
+    }
+}
+
+table! {
+    groups (id) {
+        id -> Integer,
+        name -> Text,
+        quota -> Nullable<BigInt>,
+        created_at -> BigInt,
+        used_bytes -> BigInt,
+    }
+}
+
+table! {
+    invitations (id) {
+        id -> Integer,
+        code_hash -> Text,
+        created_by -> Integer,
+        email -> Nullable<Text>,
+        max_uses -> Integer,
+        uses -> Integer,
+        created_at -> BigInt,
+        expires_at -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    login_throttles (kind, value) {
+        kind -> Text,
+        value -> Text,
+        failures -> Integer,
+        last_failure -> BigInt,
+        locked_until -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    password_resets (token_hash) {
+        token_hash -> Text,
+        user_id -> Integer,
+        created_at -> BigInt,
+        expires_at -> BigInt,
+    }
+}
+
+table! {
+    pending_uploads (id) {
+        id -> Text,
+        path -> Text,
+        user_id -> Nullable<Integer>,
+        share_link -> Nullable<Text>,
+        quota_user_id -> Nullable<Integer>,
+        quota_group_id -> Nullable<Integer>,
+        created_at -> BigInt,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Integer,
+        user_id -> Integer,
+        code_hash -> Text,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Text,
+        public_id -> Text,
+        user_id -> Integer,
+        created_at -> BigInt,
+        last_seen -> BigInt,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        expires_at -> BigInt,
+        idle_timeout -> Nullable<BigInt>,
+    }
+}
+
+table! {
+    share_accesses (id) {
+        id -> Integer,
+        link -> Text,
+        accessed_at -> BigInt,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        bytes_served -> BigInt,
+        status -> Integer,
+    }
+}
+
 table! {
     shares (link) {
         link -> Text,
-        path -> Text,
+        file_id -> Nullable<Integer>,
+        expires_at -> Nullable<BigInt>,
+        password -> Nullable<Text>,
+        remaining_downloads -> Nullable<Integer>,
+        user_id -> Nullable<Integer>,
+        created_at -> BigInt,
+        kind -> Text,
+        max_file_size -> Nullable<BigInt>,
+        max_total_size -> Nullable<BigInt>,
+        uploaded_bytes -> BigInt,
+        legacy_path -> Nullable<Text>,
+    }
+}
+
+table! {
+    totp_secrets (user_id) {
+        user_id -> Integer,
+        secret -> Text,
+        enabled -> Bool,
+        last_used_step -> Nullable<BigInt>,
+        created_at -> BigInt,
     }
 }
 
@@ -11,10 +169,43 @@ table! {
         email -> Text,
         display_name -> Text,
         password -> Text,
+        email_verified -> Bool,
+        role -> Text,
+        disabled -> Bool,
+        quota -> Nullable<BigInt>,
+        used_bytes -> BigInt,
     }
 }
 
+joinable!(api_tokens -> users (user_id));
+joinable!(email_verifications -> users (user_id));
+joinable!(files -> users (owner_id));
+joinable!(group_members -> groups (group_id));
+joinable!(group_members -> users (user_id));
+joinable!(invitations -> users (created_by));
+joinable!(password_resets -> users (user_id));
+joinable!(pending_uploads -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(shares -> files (file_id));
+joinable!(shares -> users (user_id));
+joinable!(totp_secrets -> users (user_id));
+
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    email_verifications,
+    files,
+    grants,
+    group_members,
+    groups,
+    invitations,
+    login_throttles,
+    password_resets,
+    pending_uploads,
+    recovery_codes,
+    sessions,
+    share_accesses,
     shares,
+    totp_secrets,
     users,
 );
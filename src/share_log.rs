@@ -0,0 +1,88 @@
+use crate::db;
+use crate::ShareLogQueue;
+use diesel::prelude::*;
+use parking_lot::Mutex;
+use rocket::request::Request;
+use rocket::response::{self, Body, Responder};
+use std::io::{self, Read};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+/// Starts the background worker which records the number of bytes sent for accesses to shares,
+/// once their responses are over.
+pub fn start_worker(database_url: String) -> ShareLogQueue {
+    let (sender, receiver) = mpsc::channel::<(i32, u64)>();
+    thread::spawn(move || {
+        let connection =
+            SqliteConnection::establish(&database_url).expect("Could not connect to database");
+        for (access_id, bytes_served) in receiver {
+            let bytes_served = bytes_served.min(i64::MAX as u64) as i64;
+            let _ = db::file::set_bytes_served(access_id, bytes_served, &connection);
+        }
+    });
+
+    Arc::new(Mutex::new(sender))
+}
+
+/// The content of a share, which records how many bytes of it were actually sent in the access
+/// log, including when the client stops downloading it early.
+pub struct Logged<T> {
+    content: T,
+    access: Option<(i32, ShareLogQueue)>,
+}
+
+impl<T> Logged<T> {
+    /// Wraps the content of a share, sent for the given access when it was recorded.
+    pub fn new(content: T, access_id: Option<i32>, queue: &ShareLogQueue) -> Logged<T> {
+        Logged {
+            content,
+            access: access_id.map(|access_id| (access_id, queue.clone())),
+        }
+    }
+}
+
+impl<'r, T: Responder<'r>> Responder<'r> for Logged<T> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.content.respond_to(request)?;
+        if let Some((access_id, queue)) = self.access {
+            if let Some(body) = response.take_body() {
+                let counted = |body| CountedBody {
+                    body,
+                    sent: 0,
+                    access_id,
+                    queue,
+                };
+                response.set_raw_body(match body {
+                    Body::Sized(body, size) => Body::Sized(counted(body), size),
+                    Body::Chunked(body, chunk_size) => Body::Chunked(counted(body), chunk_size),
+                });
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+/// A response body which counts the bytes read from it, and queues them to be recorded once
+/// it is dropped, which happens when the response is over.
+struct CountedBody<R> {
+    body: R,
+    sent: u64,
+    access_id: i32,
+    queue: ShareLogQueue,
+}
+
+impl<R: Read> Read for CountedBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.body.read(buf)?;
+        self.sent += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R> Drop for CountedBody<R> {
+    fn drop(&mut self) {
+        let _ = self.queue.lock().send((self.access_id, self.sent));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::utils;
+use crate::ThumbnailQueue;
+use image::io::Reader;
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use parking_lot::Mutex;
+use ring::digest;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, NamedFile, Responder, Response};
+use std::fs;
+use std::io::Write;
+use std::os::raw::{c_int, c_void};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::{ptr, slice, thread};
+use tempfile::NamedTempFile;
+
+const THUMBNAIL_SIZE: u32 = 256;
+const THUMBNAIL_QUALITY: u8 = 80;
+const SUPPORTED_EXTENSIONS: [&str; 6] = ["bmp", "gif", "jpeg", "jpg", "png", "webp"];
+/// Images with more pixels than this are not decoded, as a small file can describe a huge
+/// image which would not fit in memory.
+const MAX_SOURCE_PIXELS: u64 = 64 * 1024 * 1024;
+/// Images wider or taller than this are not decoded either.
+const MAX_SOURCE_DIMENSION: u32 = 32 * 1024;
+
+/// The image format in which thumbnails are sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ThumbnailFormat {
+    Jpeg,
+    /// Smaller than JPEG, sent to clients which accept it
+    WebP,
+}
+
+impl ThumbnailFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::WebP => "webp",
+        }
+    }
+}
+
+/// A cached thumbnail, which depends on the formats accepted by the client.
+pub struct Thumbnail(pub NamedFile);
+
+impl<'r> Responder<'r> for Thumbnail {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from(self.0.respond_to(request)?)
+            .raw_header("Vary", "Accept")
+            .ok()
+    }
+}
+
+/// Starts the background worker which generates thumbnails for newly uploaded images.
+///
+/// Paths sent through the returned queue are processed one at a time so that a burst of
+/// uploads does not compete with request handlers for CPU time.
+pub fn start_worker() -> ThumbnailQueue {
+    let (sender, receiver) = mpsc::channel::<PathBuf>();
+    thread::spawn(move || {
+        for source in receiver {
+            // Failures are not fatal, the thumbnail will be generated again when it is requested
+            for format in [ThumbnailFormat::Jpeg, ThumbnailFormat::WebP].iter() {
+                let _ = thumbnail_for(&source, *format);
+            }
+        }
+    });
+
+    Arc::new(Mutex::new(sender))
+}
+
+/// Queues the generation of a thumbnail for the given file, if it is an image.
+pub fn enqueue(queue: &ThumbnailQueue, source: &Path) {
+    if is_supported_image(source) {
+        let _ = queue.lock().send(source.to_path_buf());
+    }
+}
+
+pub fn is_supported_image(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => SUPPORTED_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Returns the path of an up-to-date thumbnail of the source image in the given format,
+/// generating it if needed.
+///
+/// A cached thumbnail is considered stale when the source file was modified after it, which
+/// happens when an upload overwrites an existing image.
+pub fn thumbnail_for(source: &Path, format: ThumbnailFormat) -> Result<PathBuf, ApiError> {
+    if !source.is_file() || !is_supported_image(source) {
+        Err(CustomError::new(
+            "Thumbnails are only available for images".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    let thumbnail_path = cache_path(source, format)?;
+    if is_stale(source, &thumbnail_path)? {
+        generate(source, &thumbnail_path, format)?;
+    }
+
+    Ok(thumbnail_path)
+}
+
+fn cache_path(source: &Path, format: ThumbnailFormat) -> Result<PathBuf, ApiError> {
+    let source = source.to_str().ok_or(ApiError::InternalServerError)?;
+    let source_digest = digest::digest(&digest::SHA256, source.as_bytes());
+    let file_name: String = source_digest
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    Ok(utils::cache_root_path()
+        .join("thumbnails")
+        .join(file_name)
+        .with_extension(format.extension()))
+}
+
+fn is_stale(source: &Path, thumbnail_path: &Path) -> Result<bool, ApiError> {
+    let source_modified = fs::metadata(source)?.modified()?;
+    match fs::metadata(thumbnail_path).and_then(|m| m.modified()) {
+        Ok(thumbnail_modified) => Ok(thumbnail_modified < source_modified),
+        Err(_) => Ok(true),
+    }
+}
+
+fn generate(source: &Path, thumbnail_path: &Path, format: ThumbnailFormat) -> Result<(), ApiError> {
+    let image = decode(source)?;
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+
+    let cache_dir = thumbnail_path
+        .parent()
+        .ok_or(ApiError::InternalServerError)?;
+    fs::create_dir_all(cache_dir)?;
+
+    // Write to a temporary file first so that concurrent readers never see a partial thumbnail
+    let mut temp_file = NamedTempFile::new_in(cache_dir)?;
+    match format {
+        ThumbnailFormat::Jpeg => DynamicImage::ImageRgb8(thumbnail)
+            .write_to(&mut temp_file, ImageOutputFormat::Jpeg(THUMBNAIL_QUALITY))
+            .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?,
+        ThumbnailFormat::WebP => temp_file.write_all(&encode_webp(&thumbnail)?)?,
+    }
+    temp_file
+        .persist(thumbnail_path)
+        .map_err(|e| CustomError::new(e.to_string(), Status::InternalServerError))?;
+
+    Ok(())
+}
+
+/// Decodes an image, unless its header announces dimensions too large to be decoded safely.
+fn decode(source: &Path) -> Result<DynamicImage, ApiError> {
+    let unprocessable =
+        |e: image::ImageError| CustomError::new(e.to_string(), Status::UnprocessableEntity);
+    let (width, height) = Reader::open(source)?
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(unprocessable)?;
+    if !fits_in_memory(width, height) {
+        Err(CustomError::new(
+            "This image is too large to have a thumbnail".to_string(),
+            Status::UnprocessableEntity,
+        ))?;
+    }
+
+    let image = Reader::open(source)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(unprocessable)?;
+    Ok(image)
+}
+
+fn fits_in_memory(width: u32, height: u32) -> bool {
+    width <= MAX_SOURCE_DIMENSION
+        && height <= MAX_SOURCE_DIMENSION
+        && u64::from(width) * u64::from(height) <= MAX_SOURCE_PIXELS
+}
+
+/// Encodes an image as a lossy WebP, which the image crate cannot do.
+fn encode_webp(image: &RgbImage) -> Result<Vec<u8>, ApiError> {
+    let (width, height) = image.dimensions();
+    let mut output: *mut u8 = ptr::null_mut();
+    // The buffer holds `width * height` RGB pixels without padding, which libwebp only reads
+    let size = unsafe {
+        libwebp_sys::WebPEncodeRGB(
+            image.as_ptr(),
+            width as c_int,
+            height as c_int,
+            (width * 3) as c_int,
+            f32::from(THUMBNAIL_QUALITY),
+            &mut output,
+        )
+    };
+    if size == 0 || output.is_null() {
+        return Err(ApiError::InternalServerError);
+    }
+
+    // The output was allocated by libwebp, which has to free it
+    let encoded = unsafe { slice::from_raw_parts(output, size) }.to_vec();
+    unsafe { libwebp_sys::WebPFree(output as *mut c_void) };
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_images_too_large_to_decode() {
+        assert!(fits_in_memory(8192, 8192));
+        assert!(!fits_in_memory(MAX_SOURCE_DIMENSION + 1, 1));
+        assert!(!fits_in_memory(30_000, 30_000));
+    }
+
+    #[test]
+    fn encodes_webp() {
+        let image = RgbImage::from_pixel(3, 2, image::Rgb([200, 100, 50]));
+        let encoded = encode_webp(&image).unwrap();
+
+        assert_eq!(&encoded[..4], b"RIFF");
+        assert_eq!(&encoded[8..12], b"WEBP");
+    }
+}
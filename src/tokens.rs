@@ -0,0 +1,135 @@
+use crate::api_error::ApiError;
+use crate::models::token::Scope;
+use crate::routes::file;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::Route;
+
+/// Prefix of API tokens, which makes them easy to recognize in configuration files and logs.
+const TOKEN_PREFIX: &str = "fsh_";
+const TOKEN_BYTES: usize = 32;
+
+/// Generates a new API token, returning it along with the hash to store.
+pub fn generate_token() -> Result<(String, String), ApiError> {
+    let token = format!("{}{}", TOKEN_PREFIX, random_string()?);
+    let token_hash = hash_token(&token);
+
+    Ok((token, token_hash))
+}
+
+/// Generates a random string which is safe to use in URLs and impossible to guess.
+pub fn random_string() -> Result<String, ApiError> {
+    let mut random_bytes = [0; TOKEN_BYTES];
+    SystemRandom::new()
+        .fill(&mut random_bytes)
+        .map_err(|_| ApiError::InternalServerError)?;
+
+    Ok(base64::encode_config(
+        &random_bytes,
+        base64::URL_SAFE_NO_PAD,
+    ))
+}
+
+/// Hashes a token so that it can be looked up in the database.
+///
+/// Tokens are long random strings, so a single round of SHA-256 is enough to make sure that
+/// a leaked database does not give access to anyone's files.
+pub fn hash_token(token: &str) -> String {
+    digest::digest(&digest::SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+lazy_static! {
+    /// The routes mounted under `/file` which can be used with an API token, along with the
+    /// scope it needs for them.
+    static ref SCOPED_ROUTES: Vec<(Scope, Vec<Route>)> = vec![
+        (
+            Scope::Read,
+            routes![file::ls, file::download, file::preview, file::thumbnail],
+        ),
+        (
+            Scope::Upload,
+            routes![file::new_upload, file::upload, file::mkdir],
+        ),
+        (
+            Scope::Shares,
+            routes![
+                file::create_share,
+                file::list_shares,
+                file::inspect_share,
+                file::share_stats,
+                file::share_log,
+                file::share_qr_code,
+                file::revoke_share
+            ],
+        ),
+    ];
+}
+
+/// Returns the scope an API token needs to have to be used with the given route.
+///
+/// Routes which are not listed in `SCOPED_ROUTES`, such as the ones managing the account or its
+/// tokens, can only be used with a session.
+pub fn required_scope(route: &Route) -> Option<Scope> {
+    if route.base() != "/file" {
+        return None;
+    }
+
+    SCOPED_ROUTES
+        .iter()
+        .find(|(_, routes)| routes.iter().any(|scoped| is_mounted(route, scoped)))
+        .map(|(scope, _)| *scope)
+}
+
+/// Returns whether a mounted route was created from the given one, which was not mounted.
+fn is_mounted(mounted: &Route, route: &Route) -> bool {
+    mounted.name == route.name
+        && mounted.method == route.method
+        && mounted.uri.path() == format!("{}{}", mounted.base(), route.uri.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::{file, user};
+
+    fn mounted_scopes(base: &str, routes: Vec<Route>) -> Vec<(&'static str, Option<Scope>)> {
+        rocket::ignite()
+            .mount(base, routes)
+            .routes()
+            .map(|route| (route.name.unwrap(), required_scope(route)))
+            .collect()
+    }
+
+    #[test]
+    fn file_routes_require_their_scope() {
+        let scopes = mounted_scopes(
+            "/file",
+            routes![
+                file::download,
+                file::mkdir,
+                file::revoke_share,
+                file::download_shared,
+                file::unlock_shared
+            ],
+        );
+
+        assert_eq!(scopes.len(), 5);
+        assert!(scopes.contains(&("download", Some(Scope::Read))));
+        assert!(scopes.contains(&("mkdir", Some(Scope::Upload))));
+        assert!(scopes.contains(&("revoke_share", Some(Scope::Shares))));
+        assert!(scopes.contains(&("download_shared", None)));
+        assert!(scopes.contains(&("unlock_shared", None)));
+    }
+
+    #[test]
+    fn other_routes_require_a_session() {
+        let scopes = mounted_scopes("/user", routes![user::me, user::create_token, file::ls]);
+
+        assert_eq!(scopes.len(), 3);
+        assert!(scopes.iter().all(|(_, scope)| scope.is_none()));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::api_error::{ApiError, CustomError};
+use crate::db;
+use crate::tokens;
+use crate::utils;
+use base32::Alphabet;
+use diesel::SqliteConnection;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac};
+use rocket::http::uri::Uri;
+use rocket::http::Status;
+
+/// Name of the service shown by authenticator applications.
+const ISSUER: &str = "Filesha";
+const SECRET_BYTES: usize = 20;
+/// Number of seconds during which a code is valid, as in RFC 6238.
+const STEP_SECONDS: i64 = 30;
+const CODE_DIGITS: usize = 6;
+/// Number of steps before and after the current one whose codes are accepted, to allow for
+/// clock drift and for the time it takes to type a code.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_BYTES: usize = 10;
+
+const BASE32: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// Generates a new random secret, encoded in base32.
+pub fn generate_secret() -> Result<String, ApiError> {
+    Ok(base32::encode(BASE32, &random_bytes(SECRET_BYTES)?))
+}
+
+/// Returns the URI authenticator applications use to set up an account, usually from a QR code.
+pub fn provisioning_uri(secret: &str, email: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{email}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = ISSUER,
+        email = Uri::percent_encode(email),
+        secret = secret,
+        digits = CODE_DIGITS,
+        period = STEP_SECONDS,
+    )
+}
+
+/// Checks a code against a secret, returning the time step it belongs to if it is valid.
+///
+/// Codes of steps up to `last_used_step` are rejected, so that a code can only be used once.
+pub fn verify_code(secret: &str, code: &str, last_used_step: Option<i64>) -> Option<i64> {
+    verify_code_at(secret, code, last_used_step, utils::unix_timestamp())
+}
+
+fn verify_code_at(secret: &str, code: &str, last_used_step: Option<i64>, now: i64) -> Option<i64> {
+    if code.len() != CODE_DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let key = base32::decode(BASE32, secret)?;
+    let current_step = now / STEP_SECONDS;
+
+    (current_step - ALLOWED_DRIFT_STEPS..=current_step + ALLOWED_DRIFT_STEPS)
+        .filter(|step| last_used_step.map_or(true, |last_used_step| *step > last_used_step))
+        .find(|step| code_at(&key, *step) == code)
+}
+
+/// Computes the HOTP value (RFC 4226) of a time step.
+fn code_at(key: &[u8], step: i64) -> String {
+    let signing_key = hmac::SigningKey::new(&digest::SHA1, key);
+    let signature = hmac::sign(&signing_key, &(step as u64).to_be_bytes());
+    let hash = signature.as_ref();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(CODE_DIGITS as u32),
+        width = CODE_DIGITS
+    )
+}
+
+/// Generates a new set of recovery codes, returned along with their hashes.
+pub fn generate_recovery_codes() -> Result<Vec<(String, String)>, ApiError> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let encoded =
+                base32::encode(BASE32, &random_bytes(RECOVERY_CODE_BYTES)?).to_lowercase();
+            let code = encoded
+                .as_bytes()
+                .chunks(4)
+                .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+                .collect::<Vec<String>>()
+                .join("-");
+            let code_hash = hash_recovery_code(&code);
+
+            Ok((code, code_hash))
+        })
+        .collect()
+}
+
+/// Hashes a recovery code, ignoring its case and separators so that it can be typed freely.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    // Recovery codes have 80 bits of entropy, so they can be hashed just like API tokens
+    tokens::hash_token(&normalized)
+}
+
+/// Checks the second factor of a user who enabled two-factor authentication, which is either a
+/// code from their authenticator application or one of their recovery codes, consuming it.
+///
+/// Users who did not enable two-factor authentication pass this check without any code.
+pub fn verify_second_factor(
+    user_id: i32,
+    code: Option<&str>,
+    conn: &SqliteConnection,
+) -> Result<(), ApiError> {
+    let secret = match db::totp::get_secret(user_id, conn)? {
+        Some(secret) if secret.enabled => secret,
+        _ => return Ok(()),
+    };
+    let code = code.map(str::trim).ok_or_else(|| {
+        CustomError::new(
+            "A two-factor authentication code is required".to_string(),
+            Status::Unauthorized,
+        )
+    })?;
+
+    let valid = match verify_code(&secret.secret, code, secret.last_used_step) {
+        Some(step) => db::totp::record_used_step(user_id, step, conn)?,
+        None => db::totp::use_recovery_code(user_id, &hash_recovery_code(code), conn)?,
+    };
+    if !valid {
+        Err(CustomError::new(
+            "Invalid two-factor authentication code".to_string(),
+            Status::Unauthorized,
+        ))?;
+    }
+
+    Ok(())
+}
+
+fn random_bytes(count: usize) -> Result<Vec<u8>, ApiError> {
+    let mut bytes = vec![0; count];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| ApiError::InternalServerError)?;
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The secret of the test vectors of RFC 6238, "12345678901234567890" in base32
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn computes_the_codes_of_rfc_6238() {
+        let key = base32::decode(BASE32, RFC_SECRET).unwrap();
+        assert_eq!(code_at(&key, 59 / STEP_SECONDS), "287082");
+        assert_eq!(code_at(&key, 1_111_111_109 / STEP_SECONDS), "081804");
+        assert_eq!(code_at(&key, 1_111_111_111 / STEP_SECONDS), "050471");
+        assert_eq!(code_at(&key, 1_234_567_890 / STEP_SECONDS), "005924");
+        assert_eq!(code_at(&key, 2_000_000_000 / STEP_SECONDS), "279037");
+    }
+
+    #[test]
+    fn accepts_codes_of_neighbouring_steps() {
+        assert_eq!(verify_code_at(RFC_SECRET, "287082", None, 59), Some(1));
+        assert_eq!(verify_code_at(RFC_SECRET, "287082", None, 89), Some(1));
+        assert_eq!(verify_code_at(RFC_SECRET, "287082", None, 0), Some(1));
+        assert_eq!(verify_code_at(RFC_SECRET, "287082", None, 90), None);
+        assert_eq!(verify_code_at(RFC_SECRET, "287083", None, 59), None);
+        assert_eq!(verify_code_at(RFC_SECRET, "28708", None, 59), None);
+        assert_eq!(verify_code_at(RFC_SECRET, "28708a", None, 59), None);
+    }
+
+    #[test]
+    fn rejects_codes_which_were_already_used() {
+        assert_eq!(verify_code_at(RFC_SECRET, "287082", Some(0), 59), Some(1));
+        assert_eq!(verify_code_at(RFC_SECRET, "287082", Some(1), 59), None);
+        assert_eq!(verify_code_at(RFC_SECRET, "287082", Some(2), 59), None);
+    }
+
+    #[test]
+    fn normalizes_recovery_codes() {
+        let hash = hash_recovery_code("abcd-efgh-ijkl-mnop");
+        assert_eq!(hash_recovery_code("ABCD EFGH IJKL MNOP"), hash);
+        assert_eq!(hash_recovery_code(" abcdefghijklmnop "), hash);
+        assert_ne!(hash_recovery_code("abcd-efgh-ijkl-mnoq"), hash);
+    }
+}
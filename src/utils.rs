@@ -1,13 +1,11 @@
-use crate::api_error::ApiError;
-use crate::models::file::PendingUpload;
-use crate::models::user::{ActiveSession, User};
-use std::collections::HashMap;
+use crate::api_error::{ApiError, CustomError};
+use rocket::http::Status;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::{ErrorKind, Read, Write};
-use std::path::{PathBuf, Path};
-use std::time::{Duration, Instant};
-use uuid::Uuid;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use walkdir::WalkDir;
 use zip::result::{ZipError, ZipResult};
 use zip::write::{FileOptions, ZipWriter};
@@ -16,11 +14,286 @@ const TINY_FILE_BUF_SIZE: usize = 8 * 1024;
 const SMALL_FILE_BUF_SIZE: usize = 16 * 1024;
 const MEDIUM_FILE_SIZE: usize = 256 * 1024;
 const LARGE_FILE_BIF_SIZE: usize = 2 * 1024 * 1024;
+const MAX_NAME_COLLISIONS: u32 = 1000;
+const DEFAULT_SHARE_LOG_RETENTION_DAYS: i64 = 90;
+const DEFAULT_SESSION_LIFETIME_HOURS: i64 = 7 * 24;
+const DEFAULT_REMEMBERED_SESSION_LIFETIME_DAYS: i64 = 30;
+/// Keeps the expiry dates of session cookies within the range of dates they can hold
+const MAX_SESSION_LIFETIME: i64 = 100 * 365 * 24 * 60 * 60;
+const DEFAULT_MAX_LOGIN_FAILURES: i64 = 5;
+const DEFAULT_MAX_LOGIN_FAILURES_PER_IP: i64 = 20;
+const DEFAULT_LOGIN_LOCKOUT_SECONDS: i64 = 60;
+const DEFAULT_PASSWORD_MIN_LENGTH: i64 = 8;
 
-pub fn user_root_path(user: &User) -> Result<PathBuf, ApiError> {
+/// A tree of files with its own directory in the storage root.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Namespace {
+    /// The private files of a user
+    User(i32),
+    /// The files of a group, which belong to none of its members
+    Group(i32),
+}
+
+/// Returns the directory where the files of a namespace are stored.
+///
+/// User directories are named after the user's ID while group directories are inside a
+/// `groups` directory, so that the two can never collide.
+pub fn root_path(namespace: Namespace) -> PathBuf {
     let storage_root = env::var("STORAGE_LOCATION").unwrap();
 
-    Ok(PathBuf::from(format!("{}/{}", storage_root, user.id)))
+    match namespace {
+        Namespace::User(user_id) => PathBuf::from(format!("{}/{}", storage_root, user_id)),
+        Namespace::Group(group_id) => {
+            PathBuf::from(format!("{}/groups/{}", storage_root, group_id))
+        }
+    }
+}
+
+/// Returns the directory where generated files (such as thumbnails) are cached.
+///
+/// This is `CACHE_LOCATION` when it is set, or a hidden directory inside the storage root
+/// otherwise, which can never collide with a user's directory.
+pub fn cache_root_path() -> PathBuf {
+    match env::var("CACHE_LOCATION") {
+        Ok(cache_root) => PathBuf::from(cache_root),
+        Err(_) => PathBuf::from(format!("{}/.cache", env::var("STORAGE_LOCATION").unwrap())),
+    }
+}
+
+/// Returns the current time as the number of seconds since the unix epoch, which is how
+/// points in time are stored in the database.
+pub fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Returns the timestamp a number of seconds after another one, capped to the latest one which
+/// can be stored.
+pub fn timestamp_after(timestamp: i64, seconds: u64) -> i64 {
+    timestamp.saturating_add(seconds.min(i64::MAX as u64) as i64)
+}
+
+/// Returns the number of days during which accesses to shares are kept, which is
+/// `SHARE_LOG_RETENTION_DAYS` when it is set.
+pub fn share_log_retention_days() -> i64 {
+    env_number("SHARE_LOG_RETENTION_DAYS").unwrap_or(DEFAULT_SHARE_LOG_RETENTION_DAYS)
+}
+
+/// Returns the number of seconds after which a session expires however it is used, which is
+/// `SESSION_LIFETIME_HOURS`, or `REMEMBERED_SESSION_LIFETIME_DAYS` for sessions opened with
+/// "remember me", when they are set.
+pub fn session_lifetime(remember: bool) -> i64 {
+    let lifetime = if remember {
+        env_number("REMEMBERED_SESSION_LIFETIME_DAYS")
+            .unwrap_or(DEFAULT_REMEMBERED_SESSION_LIFETIME_DAYS)
+            .saturating_mul(24 * 60 * 60)
+    } else {
+        env_number("SESSION_LIFETIME_HOURS")
+            .unwrap_or(DEFAULT_SESSION_LIFETIME_HOURS)
+            .saturating_mul(60 * 60)
+    };
+
+    lifetime.min(MAX_SESSION_LIFETIME)
+}
+
+/// Returns the number of seconds after which an unused session expires, which is
+/// `SESSION_IDLE_TIMEOUT_MINUTES` when it is set. Sessions opened with "remember me" never
+/// expire because of inactivity.
+pub fn session_idle_timeout(remember: bool) -> Option<i64> {
+    if remember {
+        return None;
+    }
+
+    env_number("SESSION_IDLE_TIMEOUT_MINUTES").map(|minutes| minutes.saturating_mul(60))
+}
+
+/// Returns the number of failed login attempts after which logins are refused for a while,
+/// which is `LOGIN_MAX_FAILURES` for an account or a share link, or `LOGIN_MAX_FAILURES_PER_IP`
+/// for a client address when they are set.
+pub fn max_login_failures(per_ip: bool) -> i64 {
+    if per_ip {
+        env_number("LOGIN_MAX_FAILURES_PER_IP").unwrap_or(DEFAULT_MAX_LOGIN_FAILURES_PER_IP)
+    } else {
+        env_number("LOGIN_MAX_FAILURES").unwrap_or(DEFAULT_MAX_LOGIN_FAILURES)
+    }
+}
+
+/// Returns the number of seconds during which logins are refused once there were too many
+/// failed attempts, which is `LOGIN_LOCKOUT_SECONDS` when it is set. This doubles with every
+/// further failed attempt.
+pub fn login_lockout_seconds() -> i64 {
+    env_number("LOGIN_LOCKOUT_SECONDS").unwrap_or(DEFAULT_LOGIN_LOCKOUT_SECONDS)
+}
+
+/// Returns the number of characters new passwords must at least contain, which is
+/// `PASSWORD_MIN_LENGTH` when it is set.
+pub fn password_min_length() -> usize {
+    env_number("PASSWORD_MIN_LENGTH")
+        .filter(|length| *length >= 0)
+        .unwrap_or(DEFAULT_PASSWORD_MIN_LENGTH) as usize
+}
+
+/// Returns the score, from 0 to 4, that the estimated strength of new passwords must at least
+/// reach, which is `PASSWORD_MIN_STRENGTH`. Strength is not checked when it is not set.
+pub fn password_min_strength() -> Option<u8> {
+    env_number("PASSWORD_MIN_STRENGTH").map(|score| score.clamp(0, 4) as u8)
+}
+
+/// Returns an email address the way it is stored, which is trimmed and in lowercase so that
+/// addresses are compared without case.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Returns whether an email address is listed in the comma-separated `ADMIN_EMAILS`, see
+/// `User::role`.
+pub fn is_admin(email: &str) -> bool {
+    env::var("ADMIN_EMAILS").map_or(false, |admins| {
+        admins
+            .split(',')
+            .any(|admin| admin.trim().eq_ignore_ascii_case(email))
+    })
+}
+
+/// Returns whether requests from the given address come from a reverse proxy, which is the case
+/// when it is listed in the comma-separated `TRUSTED_PROXIES`.
+pub fn is_trusted_proxy(ip: IpAddr) -> bool {
+    env::var("TRUSTED_PROXIES").map_or(false, |proxies| {
+        proxies
+            .split(',')
+            .any(|proxy| proxy.trim().parse::<IpAddr>() == Ok(ip))
+    })
+}
+
+/// Returns whether users must verify their email address before uploading files, which is
+/// the case when `REQUIRE_EMAIL_VERIFICATION` is set.
+pub fn email_verification_required() -> bool {
+    env::var("REQUIRE_EMAIL_VERIFICATION").is_ok()
+}
+
+/// Reads a number from an environment variable, if it is set to one.
+pub fn env_number(name: &str) -> Option<i64> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+}
+
+/// Formats a client address for the access log of shares.
+///
+/// When `SHARE_LOG_ANONYMIZE_IPS` is set, the last byte of IPv4 addresses and the last 80 bits
+/// of IPv6 addresses are cleared, so that clients can no longer be identified individually.
+pub fn log_ip(ip: IpAddr) -> String {
+    if env::var("SHARE_LOG_ANONYMIZE_IPS").is_err() {
+        return ip.to_string();
+    }
+
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Ipv4Addr::new(a, b, c, 0).to_string()
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0).to_string()
+        }
+    }
+}
+
+/// Joins a relative path to a root directory, making sure that the result cannot escape the
+/// root, either through the path itself or through symbolic links.
+pub fn resolve_within(root: &Path, relative: &Path) -> Result<PathBuf, ApiError> {
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        Err(CustomError::new(
+            "Paths must stay within the shared directory".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    let path = root.join(relative);
+    let canonical_root = root.canonicalize()?;
+    let canonical_path = path
+        .canonicalize()
+        .map_err(|_| CustomError::new("Not found".to_string(), Status::NotFound))?;
+    if !canonical_path.starts_with(&canonical_root) {
+        Err(CustomError::new(
+            "Paths must stay within the shared directory".to_string(),
+            Status::BadRequest,
+        ))?;
+    }
+
+    Ok(path)
+}
+
+/// Returns where a path ends up when `from` is moved to `to`, or `None` if the move does not
+/// affect it. All paths are relative to the same directory.
+pub fn moved_path(path: &str, from: &str, to: &str) -> Option<String> {
+    let rest = Path::new(path).strip_prefix(from).ok()?;
+    if rest.as_os_str().is_empty() {
+        Some(to.to_string())
+    } else {
+        Path::new(to)
+            .join(rest)
+            .to_str()
+            .map(|path| path.to_string())
+    }
+}
+
+/// Returns the total size of the files in a directory and its subdirectories.
+pub fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Creates a new file at the given path, or next to it with a number appended to its name if
+/// the path is taken, so that existing files are never overwritten.
+///
+/// Files are created atomically, so concurrent calls with the same path never return the
+/// same file.
+pub fn create_unique_file(path: &Path) -> Result<(File, PathBuf), ApiError> {
+    let parent = path.parent().ok_or(ApiError::InternalServerError)?;
+    let stem = path
+        .file_stem()
+        .ok_or(ApiError::InternalServerError)?
+        .to_string_lossy();
+    fs::create_dir_all(parent)?;
+
+    for attempt in 0..MAX_NAME_COLLISIONS {
+        let candidate = match (attempt, path.extension()) {
+            (0, _) => path.to_path_buf(),
+            (_, Some(extension)) => parent.join(format!(
+                "{} ({}).{}",
+                stem,
+                attempt,
+                extension.to_string_lossy()
+            )),
+            (_, None) => parent.join(format!("{} ({})", stem, attempt)),
+        };
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err(ApiError::from(CustomError::new(
+        "Too many files with the same name already exist".to_string(),
+        Status::Conflict,
+    )))
 }
 
 pub fn ensure_all_env_vars_are_set() -> Result<(), ApiError> {
@@ -36,34 +309,6 @@ pub fn ensure_all_env_vars_are_set() -> Result<(), ApiError> {
     Ok(())
 }
 
-pub fn remove_old_pending_uploads(
-    pending_uploads: &HashMap<Uuid, PendingUpload>,
-) -> HashMap<Uuid, PendingUpload> {
-    let seconds_in_a_day = 60 * 60 * 24; // Keep pending uploads for one day before getting rid of them
-    pending_uploads
-        .into_iter()
-        .filter(|(_uuid, pending_upload)| {
-            let duration_since = Instant::now().duration_since(pending_upload.created);
-            duration_since < Duration::new(seconds_in_a_day, 0)
-        })
-        .map(|(uuid, v)| (uuid.clone(), v.clone()))
-        .collect()
-}
-
-pub fn remove_old_sessions(
-    active_sessions: &HashMap<Uuid, ActiveSession>,
-) -> HashMap<Uuid, ActiveSession> {
-    let seconds_in_a_day = 60 * 60 * 24 * 7; // Keep sessions for one week before getting rid of them
-    active_sessions
-        .into_iter()
-        .filter(|(_uuid, pending_upload)| {
-            let duration_since = Instant::now().duration_since(pending_upload.created);
-            duration_since < Duration::new(seconds_in_a_day, 0)
-        })
-        .map(|(uuid, v)| (uuid.clone(), v.clone()))
-        .collect()
-}
-
 pub fn zip_dir_recursive(source_dir: &Path, destination_file: &File) -> ZipResult<()> {
     if !source_dir.is_dir() {
         return Err(ZipError::FileNotFound);
@@ -112,3 +357,15 @@ pub fn zip_dir_recursive(source_dir: &Path, destination_file: &File) -> ZipResul
     zip.finish()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_timestamps_far_in_the_future() {
+        assert_eq!(timestamp_after(1_000, 60), 1_060);
+        assert_eq!(timestamp_after(1_000, u64::MAX), i64::MAX);
+        assert_eq!(timestamp_after(1_000, i64::MAX as u64 + 1), i64::MAX);
+    }
+}